@@ -1,7 +1,7 @@
-#[path = "../classifiers.rs"]
-mod classifiers;
+#[path = "../syllables.rs"]
+mod syllables;
 use std::io::{self, Read};
-use classifiers::SYLLABLES;
+use syllables::SYLLABLES;
 
 fn bytes_to_syllables(bytes: &[u8]) -> String {
     let mut out = String::new();
@@ -0,0 +1,364 @@
+use crate::classifiers::DateShiftMode;
+use crate::error::OnError;
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// Environment variable consulted for the obfuscation key when neither
+/// `--key` nor `--key-file` is given.
+pub const KEY_ENV: &str = "PIPEFOG_KEY";
+
+/// Usage text printed for `--help` and on invalid arguments.
+pub const USAGE: &str = "\
+Usage: pipefog [OPTIONS]
+
+Reads JSON or YAML from stdin and writes the obfuscated documents to stdout.
+YAML comments are dropped and anchors and aliases are expanded in the output.
+
+Options:
+  -f, --format <FORMAT>          auto (default), json, jsonl (one record per line) or yaml
+  -k, --key <SECRET>             Key for HMAC-SHA3-256 obfuscation (or set PIPEFOG_KEY)
+      --key-file <PATH>          Read the obfuscation key from a file
+  -r, --rules <PATH>             YAML file with path-based keep/obfuscate/redact rules
+      --obfuscate-keys           Obfuscate the keys of every object, not just values
+      --date-mode <MODE>         shift (default, keep order and intervals), mirror or
+                                 jitter (keep order, stretch intervals)
+      --seed <SEED>              Derive the date baseline from SEED instead of at random
+      --shift-uuid-time          Shift v1/v7 UUID timestamps like datetimes instead of hashing
+      --keep-email-domain <D>    Keep the domain of emails at D (repeatable, comma-separated)
+      --keep-card-bin            Keep the 6-digit BIN of payment card numbers
+      --preserve-format          Keep the JSON input's layout and change only obfuscated values
+      --on-error <POLICY>        fail, skip or passthrough malformed input (default:
+                                 skip for jsonl, fail otherwise)
+  -h, --help                     Print this help
+";
+
+/// Serialization format used for reading and writing documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Sniff the first non-whitespace bytes of the input.
+    Auto,
+    Json,
+    /// Newline-delimited JSON, one compact record per line.
+    JsonLines,
+    Yaml,
+}
+
+impl Format {
+    fn parse(value: &str) -> Result<Format, CliError> {
+        match value {
+            "auto" => Ok(Format::Auto),
+            "json" => Ok(Format::Json),
+            "jsonl" | "ndjson" => Ok(Format::JsonLines),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            _ => Err(CliError::InvalidValue("--format", value.to_string())),
+        }
+    }
+}
+
+fn parse_date_mode(value: &str) -> Result<DateShiftMode, CliError> {
+    match value {
+        "mirror" => Ok(DateShiftMode::Mirror),
+        "shift" => Ok(DateShiftMode::Shift),
+        "jitter" => Ok(DateShiftMode::Jitter),
+        _ => Err(CliError::InvalidValue("--date-mode", value.to_string())),
+    }
+}
+
+fn parse_on_error(value: &str) -> Result<OnError, CliError> {
+    match value {
+        "fail" => Ok(OnError::Fail),
+        "skip" => Ok(OnError::Skip),
+        "passthrough" => Ok(OnError::Passthrough),
+        _ => Err(CliError::InvalidValue("--on-error", value.to_string())),
+    }
+}
+
+/// Options collected from the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub format: Format,
+    pub key: Option<String>,
+    pub key_file: Option<String>,
+    pub rules: Option<String>,
+    pub obfuscate_keys: bool,
+    pub date_mode: DateShiftMode,
+    pub seed: Option<String>,
+    pub shift_uuid_time: bool,
+    pub kept_email_domains: Vec<String>,
+    pub keep_card_bin: bool,
+    pub preserve_format: bool,
+    pub on_error: Option<OnError>,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            format: Format::Auto,
+            key: None,
+            key_file: None,
+            rules: None,
+            obfuscate_keys: false,
+            date_mode: DateShiftMode::Shift,
+            seed: None,
+            shift_uuid_time: false,
+            kept_email_domains: Vec::new(),
+            keep_card_bin: false,
+            preserve_format: false,
+            on_error: None,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Resolve the obfuscation key. `--key-file` takes precedence over
+    /// `--key`, which takes precedence over the `PIPEFOG_KEY` environment
+    /// variable. A trailing newline in the key file is ignored.
+    pub fn resolve_key(&self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(path) = &self.key_file {
+            let mut key = fs::read(path)?;
+            while matches!(key.last(), Some(b'\n' | b'\r')) {
+                key.pop();
+            }
+            return Ok(Some(key));
+        }
+        if let Some(key) = &self.key {
+            return Ok(Some(key.as_bytes().to_vec()));
+        }
+        Ok(std::env::var(KEY_ENV)
+            .ok()
+            .filter(|key| !key.is_empty())
+            .map(String::into_bytes))
+    }
+
+    /// The error policy for `format`. Without `--on-error`, JSON Lines
+    /// reports a bad line and moves on, since one stray log line should not
+    /// end a `tail -f`; every other format stops at the first error.
+    pub fn on_error_for(&self, format: Format) -> OnError {
+        self.on_error.unwrap_or(match format {
+            Format::JsonLines => OnError::Skip,
+            _ => OnError::Fail,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError {
+    UnknownArgument(String),
+    MissingValue(&'static str),
+    InvalidValue(&'static str, String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::UnknownArgument(arg) => write!(f, "unknown argument: {}", arg),
+            CliError::MissingValue(flag) => write!(f, "missing value for {}", flag),
+            CliError::InvalidValue(flag, value) => {
+                write!(f, "invalid value for {}: {}", flag, value)
+            }
+        }
+    }
+}
+
+/// Split `--flag=value` into its parts, leaving plain flags untouched.
+fn split_flag(arg: &str) -> (&str, Option<&str>) {
+    if arg.starts_with("--") {
+        if let Some((flag, value)) = arg.split_once('=') {
+            return (flag, Some(value));
+        }
+    }
+    (arg, None)
+}
+
+/// Parse command line arguments, excluding the program name.
+pub fn parse_args<I>(args: I) -> Result<Options, CliError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        let mut value = |name: &'static str| -> Result<String, CliError> {
+            match inline {
+                Some(v) => Ok(v.to_string()),
+                None => args.next().ok_or(CliError::MissingValue(name)),
+            }
+        };
+        match flag {
+            "-f" | "--format" => options.format = Format::parse(&value("--format")?)?,
+            "-k" | "--key" => options.key = Some(value("--key")?),
+            "--key-file" => options.key_file = Some(value("--key-file")?),
+            "-r" | "--rules" => options.rules = Some(value("--rules")?),
+            "--obfuscate-keys" => options.obfuscate_keys = true,
+            "--date-mode" => options.date_mode = parse_date_mode(&value("--date-mode")?)?,
+            "--seed" => options.seed = Some(value("--seed")?),
+            "--shift-uuid-time" => options.shift_uuid_time = true,
+            "--keep-email-domain" => options.kept_email_domains.extend(
+                value("--keep-email-domain")?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|d| !d.is_empty())
+                    .map(str::to_string),
+            ),
+            "--keep-card-bin" => options.keep_card_bin = true,
+            "--preserve-format" => options.preserve_format = true,
+            "--on-error" => options.on_error = Some(parse_on_error(&value("--on-error")?)?),
+            "-h" | "--help" => options.help = true,
+            _ => return Err(CliError::UnknownArgument(arg.clone())),
+        }
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, CliError> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args_defaults() {
+        assert_eq!(parse(&[]).unwrap(), Options::default());
+    }
+
+    #[test]
+    fn test_parse_args_format() {
+        assert_eq!(parse(&["--format", "yaml"]).unwrap().format, Format::Yaml);
+        assert_eq!(parse(&["--format=json"]).unwrap().format, Format::Json);
+        assert_eq!(
+            parse(&["--format", "ndjson"]).unwrap().format,
+            Format::JsonLines
+        );
+        assert_eq!(parse(&["-f", "auto"]).unwrap().format, Format::Auto);
+        assert_eq!(
+            parse(&["--format", "xml"]),
+            Err(CliError::InvalidValue("--format", "xml".to_string()))
+        );
+        assert_eq!(
+            parse(&["--format"]),
+            Err(CliError::MissingValue("--format"))
+        );
+    }
+
+    #[test]
+    fn test_parse_args_key() {
+        let options = parse(&["--key", "secret", "--key-file=/tmp/key"]).unwrap();
+        assert_eq!(options.key.as_deref(), Some("secret"));
+        assert_eq!(options.key_file.as_deref(), Some("/tmp/key"));
+        assert_eq!(parse(&["-k"]), Err(CliError::MissingValue("--key")));
+    }
+
+    #[test]
+    fn test_parse_args_rules() {
+        let options = parse(&["-r", "policy.yaml"]).unwrap();
+        assert_eq!(options.rules.as_deref(), Some("policy.yaml"));
+        assert!(!options.obfuscate_keys);
+        assert!(parse(&["--obfuscate-keys"]).unwrap().obfuscate_keys);
+    }
+
+    #[test]
+    fn test_resolve_key_from_file() {
+        let path = std::env::temp_dir().join(format!("pipefog-key-{}", std::process::id()));
+        fs::write(&path, "file secret\n").unwrap();
+        let options = Options {
+            key: Some("ignored".to_string()),
+            key_file: Some(path.to_string_lossy().into_owned()),
+            ..Options::default()
+        };
+        let key = options.resolve_key();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(key.unwrap(), Some(b"file secret".to_vec()));
+    }
+
+    #[test]
+    fn test_parse_args_date_mode() {
+        assert_eq!(
+            parse(&["--date-mode", "shift"]).unwrap().date_mode,
+            DateShiftMode::Shift
+        );
+        assert_eq!(
+            parse(&["--date-mode=jitter"]).unwrap().date_mode,
+            DateShiftMode::Jitter
+        );
+        assert_eq!(
+            parse(&["--date-mode", "reverse"]),
+            Err(CliError::InvalidValue("--date-mode", "reverse".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_args_seed() {
+        assert_eq!(
+            parse(&["--seed", "42"]).unwrap().seed.as_deref(),
+            Some("42")
+        );
+        assert_eq!(parse(&["--seed"]), Err(CliError::MissingValue("--seed")));
+    }
+
+    #[test]
+    fn test_parse_args_shift_uuid_time() {
+        assert!(!parse(&[]).unwrap().shift_uuid_time);
+        assert!(parse(&["--shift-uuid-time"]).unwrap().shift_uuid_time);
+    }
+
+    #[test]
+    fn test_parse_args_kept_email_domains() {
+        let options = parse(&[
+            "--keep-email-domain",
+            "gmail.com, example.com",
+            "--keep-email-domain=outlook.com",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.kept_email_domains,
+            vec!["gmail.com", "example.com", "outlook.com"]
+        );
+    }
+
+    #[test]
+    fn test_parse_args_keep_card_bin() {
+        assert!(!parse(&[]).unwrap().keep_card_bin);
+        assert!(parse(&["--keep-card-bin"]).unwrap().keep_card_bin);
+    }
+
+    #[test]
+    fn test_parse_args_preserve_format() {
+        assert!(!parse(&[]).unwrap().preserve_format);
+        assert!(parse(&["--preserve-format"]).unwrap().preserve_format);
+    }
+
+    #[test]
+    fn test_parse_args_on_error() {
+        let default = parse(&[]).unwrap();
+        assert_eq!(default.on_error, None);
+        assert_eq!(default.on_error_for(Format::Json), OnError::Fail);
+        assert_eq!(default.on_error_for(Format::Yaml), OnError::Fail);
+        assert_eq!(default.on_error_for(Format::JsonLines), OnError::Skip);
+        let fail = parse(&["--on-error", "fail"]).unwrap();
+        assert_eq!(fail.on_error_for(Format::JsonLines), OnError::Fail);
+        assert_eq!(
+            parse(&["--on-error=passthrough"]).unwrap().on_error,
+            Some(OnError::Passthrough)
+        );
+        assert_eq!(
+            parse(&["--on-error", "ignore"]),
+            Err(CliError::InvalidValue("--on-error", "ignore".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_args_unknown() {
+        assert_eq!(
+            parse(&["--frobnicate"]),
+            Err(CliError::UnknownArgument("--frobnicate".to_string()))
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Errors that end a run, and the exit code reported for each kind.
+
+use std::fmt;
+use std::io;
+
+use crate::cli::CliError;
+use crate::rules::RulesError;
+use crate::stream::{StreamError, SyntaxError};
+
+/// Invalid command line arguments.
+pub const EXIT_USAGE: i32 = 2;
+/// The key file or rules file cannot be used.
+pub const EXIT_POLICY: i32 = 3;
+/// The input is not valid JSON or YAML.
+pub const EXIT_PARSE: i32 = 4;
+/// Reading the input or writing the output failed.
+pub const EXIT_IO: i32 = 5;
+
+/// What to do with input that cannot be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop at the first malformed value.
+    Fail,
+    /// Report the malformed value on stderr and leave it out.
+    Skip,
+    /// Report the malformed value on stderr and copy it to the output as is.
+    Passthrough,
+}
+
+/// Error that ends a run.
+#[derive(Debug)]
+pub enum Error {
+    Usage(CliError),
+    KeyFile(io::Error),
+    Rules(RulesError),
+    Json(SyntaxError),
+    Yaml(serde_yaml::Error),
+    Io(io::Error),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => EXIT_USAGE,
+            Error::KeyFile(_) | Error::Rules(_) => EXIT_POLICY,
+            Error::Json(_) | Error::Yaml(_) => EXIT_PARSE,
+            Error::Io(_) => EXIT_IO,
+        }
+    }
+
+    /// Whether the reader of the output went away, as with `pipefog | head`.
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(self, Error::Io(e) if e.kind() == io::ErrorKind::BrokenPipe)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(e) => write!(f, "{}", e),
+            Error::KeyFile(e) => write!(f, "cannot read key file: {}", e),
+            Error::Rules(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "invalid JSON: {}", e),
+            Error::Yaml(e) => write!(f, "invalid YAML: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<StreamError> for Error {
+    fn from(e: StreamError) -> Self {
+        match e {
+            StreamError::Io(e) => Error::Io(e),
+            StreamError::Syntax(e) => Error::Json(e),
+        }
+    }
+}
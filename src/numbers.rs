@@ -0,0 +1,323 @@
+use crate::classifiers::{keyed_digest, keyed_permutation};
+use serde_json::Number;
+
+/// Relative noise used by `remap` for floats too large to remap digit by
+/// digit.
+const FALLBACK_NOISE: f64 = 0.1;
+
+/// Largest integer an `f64` represents exactly.
+const MAX_EXACT_FLOAT: u64 = 1 << 53;
+
+/// How numbers selected by a rule are obfuscated. Integers stay integers and
+/// floats stay floats with every strategy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberStrategy {
+    /// Keyed permutation of the digits that keeps the sign and the number of
+    /// digits, so distinct IDs stay distinct.
+    Remap,
+    /// Multiply by a keyed factor between `1 - amount` and `1 + amount`,
+    /// keeping the number of decimal places.
+    Noise(f64),
+    /// Round to the nearest multiple of the step.
+    Round(f64),
+    /// Round down to a multiple of the step.
+    Bucket(f64),
+    /// Move latitudes by a keyed offset, staying within -90..=90.
+    Latitude,
+    /// Move longitudes by a keyed offset, staying within -180..=180.
+    Longitude,
+}
+
+impl NumberStrategy {
+    /// Obfuscate `number`. Returns `None` when no representable result exists,
+    /// in which case the value should be left as is.
+    pub fn apply(&self, number: &Number) -> Option<Number> {
+        if let Some(n) = number.as_u64() {
+            self.apply_unsigned(n).map(Number::from)
+        } else if let Some(n) = number.as_i64() {
+            self.apply_signed(n).map(Number::from)
+        } else {
+            let text = number.to_string();
+            let value = number.as_f64()?;
+            Number::from_f64(self.apply_float(value, &text)?)
+        }
+    }
+
+    fn apply_unsigned(&self, n: u64) -> Option<u64> {
+        // Values that fit an i64 stay within i64 for consumers that read them
+        // as signed integers.
+        let max = if n <= i64::MAX as u64 {
+            i64::MAX as u64
+        } else {
+            u64::MAX
+        };
+        match self {
+            NumberStrategy::Remap => Some(remap_digits(n, max, b"+")),
+            _ => {
+                let out = self.apply_integer(n as i128)?;
+                Some(out.clamp(0, max as i128) as u64)
+            }
+        }
+    }
+
+    fn apply_signed(&self, n: i64) -> Option<i64> {
+        match self {
+            NumberStrategy::Remap if n < 0 => {
+                let abs = remap_digits(n.unsigned_abs(), i64::MIN.unsigned_abs(), b"-");
+                Some((-(abs as i128)) as i64)
+            }
+            NumberStrategy::Remap => Some(remap_digits(n as u64, i64::MAX as u64, b"+") as i64),
+            _ => {
+                let out = self.apply_integer(n as i128)?;
+                Some(out.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+            }
+        }
+    }
+
+    /// Apply a non-remap strategy to an integer, computing in `i128` so the
+    /// caller can clamp the result to the input's type.
+    fn apply_integer(&self, n: i128) -> Option<i128> {
+        match *self {
+            NumberStrategy::Remap => Some(n),
+            NumberStrategy::Noise(amount) => {
+                let factor = 1.0 + noise(&n.to_string()) * amount;
+                Some((n as f64 * factor).round() as i128)
+            }
+            NumberStrategy::Round(step) | NumberStrategy::Bucket(step) => {
+                let step = (step.round() as i128).max(1);
+                let lower = n.div_euclid(step) * step;
+                if matches!(self, NumberStrategy::Round(_)) && (n - lower) * 2 >= step {
+                    Some(lower + step)
+                } else {
+                    Some(lower)
+                }
+            }
+            NumberStrategy::Latitude | NumberStrategy::Longitude => {
+                Some(self.apply_float(n as f64, "0")?.round() as i128)
+            }
+        }
+    }
+
+    fn apply_float(&self, value: f64, text: &str) -> Option<f64> {
+        let places = decimal_places(text);
+        let out = match *self {
+            NumberStrategy::Remap => return remap_float(value, places),
+            NumberStrategy::Noise(amount) => round_to(value * (1.0 + noise(text) * amount), places),
+            NumberStrategy::Round(step) => round_to(
+                (value / step).round() * step,
+                decimal_places(&step.to_string()),
+            ),
+            NumberStrategy::Bucket(step) => round_to(
+                (value / step).floor() * step,
+                decimal_places(&step.to_string()),
+            ),
+            NumberStrategy::Latitude => {
+                let moved = wrap_degrees(value + coordinate_offset(b"latitude"));
+                let lat = if moved > 90.0 {
+                    180.0 - moved
+                } else if moved < -90.0 {
+                    -180.0 - moved
+                } else {
+                    moved
+                };
+                round_to(lat, places)
+            }
+            NumberStrategy::Longitude => round_to(
+                wrap_degrees(value + coordinate_offset(b"longitude")),
+                places,
+            ),
+        };
+        if out.is_finite() {
+            Some(out)
+        } else {
+            Some(f64::MAX.copysign(value))
+        }
+    }
+}
+
+/// Number of decimal places in the textual form of a number, e.g. 2 for
+/// `12.34` and 7 for `1e-7`.
+fn decimal_places(text: &str) -> i32 {
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(pos) => (&text[..pos], text[pos + 1..].parse().unwrap_or(0)),
+        None => (text, 0),
+    };
+    let fraction = mantissa.split_once('.').map_or(0, |(_, f)| f.len() as i32);
+    (fraction - exponent).max(0)
+}
+
+/// Round `value` to `places` decimal places. Values that would lose
+/// precision by scaling are returned unchanged.
+fn round_to(value: f64, places: i32) -> f64 {
+    if places > 15 {
+        return value;
+    }
+    let scale = 10f64.powi(places);
+    let scaled = (value * scale).round() / scale;
+    if scaled.is_finite() {
+        scaled
+    } else {
+        value
+    }
+}
+
+/// A keyed value in `-1.0..1.0` derived from `text`.
+fn noise(text: &str) -> f64 {
+    let mut data = b"pipefog noise ".to_vec();
+    data.extend_from_slice(text.as_bytes());
+    let hash = keyed_digest(&data);
+    let bits = u64::from_le_bytes(hash[..8].try_into().unwrap()) >> 11;
+    bits as f64 / MAX_EXACT_FLOAT as f64 * 2.0 - 1.0
+}
+
+/// Keyed offset in `0.0..360.0` shared by every coordinate of one kind, so
+/// distances between nearby points survive.
+fn coordinate_offset(kind: &[u8]) -> f64 {
+    let mut data = b"pipefog ".to_vec();
+    data.extend_from_slice(kind);
+    let hash = keyed_digest(&data);
+    let bits = u64::from_le_bytes(hash[..8].try_into().unwrap()) >> 11;
+    bits as f64 / MAX_EXACT_FLOAT as f64 * 360.0
+}
+
+/// Bring an angle into `-180.0..180.0`.
+fn wrap_degrees(degrees: f64) -> f64 {
+    (degrees + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Remap the digits of a float, keeping its sign and decimal places. Floats
+/// whose digits do not fit an `f64` exactly get relative noise instead.
+fn remap_float(value: f64, places: i32) -> Option<f64> {
+    let scale = 10f64.powi(places);
+    let scaled = (value.abs() * scale).round();
+    if places > 15 || scaled >= MAX_EXACT_FLOAT as f64 {
+        return NumberStrategy::Noise(FALLBACK_NOISE).apply_float(value, &value.to_string());
+    }
+    let tweak: &[u8] = if value < 0.0 { b"-." } else { b"+." };
+    let remapped = remap_digits(scaled as u64, MAX_EXACT_FLOAT - 1, tweak) as f64 / scale;
+    Some(remapped.copysign(value))
+}
+
+/// Map `n` to another number with the same count of decimal digits, never
+/// exceeding `max`. The mapping is a keyed permutation, so different inputs
+/// give different outputs.
+fn remap_digits(n: u64, max: u64, tweak: &[u8]) -> u64 {
+    let digits = n.checked_ilog10().map_or(1, |d| d + 1);
+    let low = if digits == 1 {
+        0
+    } else {
+        10u64.pow(digits - 1)
+    };
+    let high = 10u64
+        .checked_pow(digits)
+        .map_or(u64::MAX, |p| p - 1)
+        .min(max);
+    if n > high {
+        return n;
+    }
+    let mut domain_tweak = tweak.to_vec();
+    domain_tweak.extend_from_slice(&digits.to_le_bytes());
+    low + keyed_permutation(n - low, high - low, &domain_tweak)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn apply(strategy: NumberStrategy, json: &str) -> String {
+        let number: Number = serde_json::from_str(json).unwrap();
+        strategy.apply(&number).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_remap_keeps_digit_count_and_sign() {
+        for input in ["7", "42", "-42", "1234567", "-1000", "0"] {
+            let out = apply(NumberStrategy::Remap, input);
+            assert_eq!(out.len(), input.len(), "{} -> {}", input, out);
+            assert_eq!(out.starts_with('-'), input.starts_with('-'));
+        }
+        let max = apply(NumberStrategy::Remap, &i64::MAX.to_string());
+        assert!(max.parse::<i64>().is_ok());
+        assert_eq!(max.len(), 19);
+        let min = apply(NumberStrategy::Remap, &i64::MIN.to_string());
+        assert!(min.parse::<i64>().is_ok());
+        let huge = apply(NumberStrategy::Remap, &u64::MAX.to_string());
+        assert_eq!(huge.len(), 20);
+        assert!(huge.parse::<u64>().is_ok());
+    }
+
+    #[test]
+    fn test_remap_is_a_permutation() {
+        let outputs: HashSet<u64> = (100..1000)
+            .map(|n| remap_digits(n, u64::MAX, b"+"))
+            .collect();
+        assert_eq!(outputs.len(), 900);
+        assert!(outputs.iter().all(|n| (100..1000).contains(n)));
+    }
+
+    #[test]
+    fn test_remap_float_keeps_places() {
+        let out = apply(NumberStrategy::Remap, "-12.34");
+        assert!(out.starts_with('-'));
+        assert!(decimal_places(&out) <= 2);
+        assert!(out.parse::<f64>().unwrap().abs() < 100.0);
+        assert!(apply(NumberStrategy::Remap, "1.5e300")
+            .parse::<f64>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_noise() {
+        let out: f64 = apply(NumberStrategy::Noise(0.1), "1234.56")
+            .parse()
+            .unwrap();
+        assert!((1111.1..=1358.1).contains(&out));
+        assert!(decimal_places(&out.to_string()) <= 2);
+        let int = apply(NumberStrategy::Noise(0.5), "1000");
+        assert!(int.parse::<i64>().is_ok());
+        let max: f64 = apply(NumberStrategy::Noise(0.5), "1.7976931348623157e308")
+            .parse()
+            .unwrap();
+        assert!(max.is_finite());
+        assert!(apply(NumberStrategy::Noise(0.5), &i64::MAX.to_string())
+            .parse::<i64>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_round_and_bucket() {
+        assert_eq!(apply(NumberStrategy::Round(10.0), "37"), "40");
+        assert_eq!(apply(NumberStrategy::Bucket(10.0), "37"), "30");
+        assert_eq!(apply(NumberStrategy::Bucket(10.0), "-37"), "-40");
+        assert_eq!(apply(NumberStrategy::Round(0.1), "0.26"), "0.3");
+        assert_eq!(apply(NumberStrategy::Bucket(5.0), "12.5"), "10.0");
+        assert_eq!(
+            apply(NumberStrategy::Round(1000.0), &u64::MAX.to_string()),
+            u64::MAX.to_string()
+        );
+    }
+
+    #[test]
+    fn test_coordinates_stay_in_range() {
+        for input in ["0.0", "51.5074", "-89.9999", "90.0", "-33.8688"] {
+            let lat: f64 = apply(NumberStrategy::Latitude, input).parse().unwrap();
+            assert!((-90.0..=90.0).contains(&lat), "{} -> {}", input, lat);
+            let lon: f64 = apply(NumberStrategy::Longitude, input).parse().unwrap();
+            assert!((-180.0..=180.0).contains(&lon), "{} -> {}", input, lon);
+        }
+        assert!(apply(NumberStrategy::Longitude, "120")
+            .parse::<i64>()
+            .is_ok());
+        let out = apply(NumberStrategy::Longitude, "-0.1278");
+        assert!(decimal_places(&out) <= 4);
+    }
+
+    #[test]
+    fn test_decimal_places() {
+        assert_eq!(decimal_places("12"), 0);
+        assert_eq!(decimal_places("12.34"), 2);
+        assert_eq!(decimal_places("1e-7"), 7);
+        assert_eq!(decimal_places("1.5e3"), 0);
+    }
+}
@@ -0,0 +1,625 @@
+use crate::classifiers::{find_classifier, Classifier};
+use crate::numbers::NumberStrategy;
+use crate::secrets::{find_secret_type, SecretAction, SecretPolicy};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Placeholder written by the `redact` action when none is configured.
+pub const DEFAULT_PLACEHOLDER: &str = "[REDACTED]";
+
+/// One step in the location of a value inside a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// One step of a path pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatternSegment {
+    /// `.name` or `['name']`
+    Key(String),
+    /// `[3]`
+    Index(usize),
+    /// `[*]`, any array element.
+    AnyIndex,
+    /// `*`, any single key or array element.
+    Any,
+    /// `**` or `..`, zero or more segments.
+    AnyDepth,
+}
+
+impl PatternSegment {
+    fn matches(&self, segment: &PathSegment) -> bool {
+        match (self, segment) {
+            (PatternSegment::Key(k), PathSegment::Key(s)) => k == s,
+            (PatternSegment::Index(i), PathSegment::Index(s)) => i == s,
+            (PatternSegment::AnyIndex, PathSegment::Index(_)) => true,
+            (PatternSegment::Any, _) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A JSONPath-like pattern such as `$.items[*].vault.name` or `**.password`.
+///
+/// Supported syntax: an optional leading `$`, `.name`, `['name']`, `[3]`,
+/// `[*]` for any array element, `*` for any single key or element, and `**`
+/// (or JSONPath's `..`) for any number of levels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPattern {
+    segments: Vec<PatternSegment>,
+}
+
+impl PathPattern {
+    pub fn parse(pattern: &str) -> Result<PathPattern, String> {
+        let rest = pattern.trim();
+        let rest = rest.strip_prefix('$').unwrap_or(rest);
+        let chars: Vec<char> = rest.chars().collect();
+        let mut segments = Vec::new();
+        let mut i = 0;
+        // A bare name is allowed at the start, e.g. `**.password`.
+        let mut expect_name = !chars.is_empty() && chars[0] != '.' && chars[0] != '[';
+
+        while i < chars.len() || expect_name {
+            if !expect_name {
+                match chars[i] {
+                    '.' if chars.get(i + 1) == Some(&'.') => {
+                        segments.push(PatternSegment::AnyDepth);
+                        i += 2;
+                        expect_name = chars.get(i).is_some_and(|&c| c != '[');
+                        continue;
+                    }
+                    '.' => {
+                        i += 1;
+                        expect_name = true;
+                        continue;
+                    }
+                    '[' => {
+                        let end = chars[i..]
+                            .iter()
+                            .position(|&c| c == ']')
+                            .ok_or_else(|| "unterminated `[`".to_string())?;
+                        let inner: String = chars[i + 1..i + end].iter().collect();
+                        segments.push(parse_bracket(inner.trim())?);
+                        i += end + 1;
+                        continue;
+                    }
+                    c => return Err(format!("unexpected `{}`", c)),
+                }
+            }
+
+            let start = i;
+            while i < chars.len() && chars[i] != '.' && chars[i] != '[' {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            segments.push(match name.as_str() {
+                "" => return Err("empty key".to_string()),
+                "*" => PatternSegment::Any,
+                "**" => PatternSegment::AnyDepth,
+                _ => PatternSegment::Key(name),
+            });
+            expect_name = false;
+        }
+
+        Ok(PathPattern { segments })
+    }
+
+    /// Check whether the pattern matches the full `path`.
+    pub fn matches(&self, path: &[PathSegment]) -> bool {
+        matches_from(&self.segments, path)
+    }
+}
+
+fn parse_bracket(inner: &str) -> Result<PatternSegment, String> {
+    if inner == "*" {
+        return Ok(PatternSegment::AnyIndex);
+    }
+    for quote in ['\'', '"'] {
+        if inner.len() >= 2 && inner.starts_with(quote) && inner.ends_with(quote) {
+            return Ok(PatternSegment::Key(inner[1..inner.len() - 1].to_string()));
+        }
+    }
+    inner
+        .parse()
+        .map(PatternSegment::Index)
+        .map_err(|_| format!("invalid index `{}`", inner))
+}
+
+fn matches_from(pattern: &[PatternSegment], path: &[PathSegment]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((PatternSegment::AnyDepth, rest)) => {
+            (0..=path.len()).any(|skip| matches_from(rest, &path[skip..]))
+        }
+        Some((segment, rest)) => match path.split_first() {
+            Some((head, tail)) => segment.matches(head) && matches_from(rest, tail),
+            None => false,
+        },
+    }
+}
+
+/// What to do with a value selected by a rule.
+#[derive(Clone)]
+pub enum Action {
+    /// Leave the value and everything below it untouched.
+    Keep,
+    /// Obfuscate strings, either with the default classifier chain or with
+    /// a named classifier, and numbers when a strategy is given.
+    Obfuscate(Option<&'static Classifier>, Option<NumberStrategy>),
+    /// Replace the value with a fixed string.
+    Redact(String),
+    /// Replace the value with `null`.
+    Null,
+    /// Remove the key or array element.
+    Delete,
+}
+
+pub struct Rule {
+    pub pattern: PathPattern,
+    pub action: Action,
+}
+
+/// An ordered list of rules. The first rule whose pattern matches a value's
+/// path decides what happens to it.
+#[derive(Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+    /// Objects whose keys are obfuscated in addition to their values.
+    key_patterns: Vec<PathPattern>,
+    /// Obfuscate the keys of every object, regardless of `key_patterns`.
+    pub obfuscate_all_keys: bool,
+    /// What to do with detected secrets, per secret type.
+    pub secrets: SecretPolicy,
+}
+
+#[derive(Debug)]
+pub enum RulesError {
+    Io(io::Error),
+    Parse(serde_yaml::Error),
+    InvalidPath(String, String),
+    UnknownClassifier(String),
+    UnexpectedField(&'static str, String),
+    InvalidValue(&'static str, String),
+    UnknownSecretType(String),
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesError::Io(e) => write!(f, "cannot read rules file: {}", e),
+            RulesError::Parse(e) => write!(f, "cannot parse rules file: {}", e),
+            RulesError::InvalidPath(path, reason) => {
+                write!(f, "invalid path pattern `{}`: {}", path, reason)
+            }
+            RulesError::UnknownClassifier(name) => write!(f, "unknown classifier: {}", name),
+            RulesError::UnexpectedField(field, path) => {
+                write!(f, "`{}` is not valid for the action of `{}`", field, path)
+            }
+            RulesError::InvalidValue(field, path) => {
+                write!(f, "invalid `{}` for `{}`", field, path)
+            }
+            RulesError::UnknownSecretType(name) => write!(f, "unknown secret type: {}", name),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ActionName {
+    Keep,
+    Obfuscate,
+    Redact,
+    Null,
+    Delete,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SecretActionName {
+    Fake,
+    Redact,
+    Off,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NumbersName {
+    Remap,
+    Noise,
+    Round,
+    Bucket,
+    Latitude,
+    Longitude,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    path: String,
+    action: ActionName,
+    classifier: Option<String>,
+    placeholder: Option<String>,
+    numbers: Option<NumbersName>,
+    noise: Option<f64>,
+    step: Option<f64>,
+}
+
+/// Relative noise applied by `numbers: noise` when `noise` is not set.
+pub const DEFAULT_NOISE: f64 = 0.1;
+
+/// Build the number strategy of `spec`, checking that `noise` and `step` are
+/// only given to the strategies that use them.
+fn number_strategy(spec: &RuleSpec) -> Result<Option<NumberStrategy>, RulesError> {
+    let path = || spec.path.clone();
+    let numbers = match &spec.numbers {
+        Some(numbers) => numbers,
+        None if spec.noise.is_some() => return Err(RulesError::UnexpectedField("noise", path())),
+        None if spec.step.is_some() => return Err(RulesError::UnexpectedField("step", path())),
+        None => return Ok(None),
+    };
+    if !matches!(spec.action, ActionName::Obfuscate) {
+        return Err(RulesError::UnexpectedField("numbers", path()));
+    }
+    if spec.noise.is_some() && !matches!(numbers, NumbersName::Noise) {
+        return Err(RulesError::UnexpectedField("noise", path()));
+    }
+    if spec.step.is_some() && !matches!(numbers, NumbersName::Round | NumbersName::Bucket) {
+        return Err(RulesError::UnexpectedField("step", path()));
+    }
+    Ok(Some(match numbers {
+        NumbersName::Remap => NumberStrategy::Remap,
+        NumbersName::Noise => {
+            let noise = spec.noise.unwrap_or(DEFAULT_NOISE);
+            if !(noise > 0.0 && noise < 1.0) {
+                return Err(RulesError::InvalidValue("noise", path()));
+            }
+            NumberStrategy::Noise(noise)
+        }
+        NumbersName::Round | NumbersName::Bucket => {
+            let step = spec.step.unwrap_or(1.0);
+            if !(step.is_finite() && step > 0.0) {
+                return Err(RulesError::InvalidValue("step", path()));
+            }
+            if matches!(numbers, NumbersName::Round) {
+                NumberStrategy::Round(step)
+            } else {
+                NumberStrategy::Bucket(step)
+            }
+        }
+        NumbersName::Latitude => NumberStrategy::Latitude,
+        NumbersName::Longitude => NumberStrategy::Longitude,
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<RuleSpec>,
+    #[serde(default)]
+    obfuscate_keys: Vec<String>,
+    #[serde(default)]
+    secrets: BTreeMap<String, SecretActionName>,
+}
+
+impl Rules {
+    /// Read rules from a YAML (or JSON) file.
+    pub fn load(path: &Path) -> Result<Rules, RulesError> {
+        let text = fs::read_to_string(path).map_err(RulesError::Io)?;
+        Rules::parse(&text)
+    }
+
+    /// Parse rules from YAML text of the form:
+    ///
+    /// ```yaml
+    /// rules:
+    ///   - path: "**.password"
+    ///     action: redact
+    ///   - path: "$.items[*].vault.name"
+    ///     action: obfuscate
+    ///     classifier: capitalized_word
+    /// ```
+    pub fn parse(text: &str) -> Result<Rules, RulesError> {
+        let file: RulesFile = serde_yaml::from_str(text).map_err(RulesError::Parse)?;
+        let mut rules = Vec::with_capacity(file.rules.len());
+
+        for spec in file.rules {
+            let pattern = PathPattern::parse(&spec.path)
+                .map_err(|reason| RulesError::InvalidPath(spec.path.clone(), reason))?;
+            if spec.classifier.is_some() && !matches!(spec.action, ActionName::Obfuscate) {
+                return Err(RulesError::UnexpectedField("classifier", spec.path));
+            }
+            if spec.placeholder.is_some() && !matches!(spec.action, ActionName::Redact) {
+                return Err(RulesError::UnexpectedField("placeholder", spec.path));
+            }
+            let numbers = number_strategy(&spec)?;
+            let action = match spec.action {
+                ActionName::Keep => Action::Keep,
+                ActionName::Obfuscate => match spec.classifier {
+                    Some(name) => Action::Obfuscate(
+                        Some(find_classifier(&name).ok_or(RulesError::UnknownClassifier(name))?),
+                        numbers,
+                    ),
+                    None => Action::Obfuscate(None, numbers),
+                },
+                ActionName::Redact => Action::Redact(
+                    spec.placeholder
+                        .unwrap_or_else(|| DEFAULT_PLACEHOLDER.to_string()),
+                ),
+                ActionName::Null => Action::Null,
+                ActionName::Delete => Action::Delete,
+            };
+            rules.push(Rule { pattern, action });
+        }
+
+        let key_patterns = file
+            .obfuscate_keys
+            .iter()
+            .map(|path| {
+                PathPattern::parse(path)
+                    .map_err(|reason| RulesError::InvalidPath(path.clone(), reason))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut secrets = SecretPolicy::default();
+        for (name, action) in file.secrets {
+            let secret_type = find_secret_type(&name).ok_or(RulesError::UnknownSecretType(name))?;
+            let action = match action {
+                SecretActionName::Fake => SecretAction::Fake,
+                SecretActionName::Redact => SecretAction::Redact,
+                SecretActionName::Off => SecretAction::Off,
+            };
+            secrets.set(secret_type.name, action);
+        }
+
+        Ok(Rules {
+            rules,
+            key_patterns,
+            obfuscate_all_keys: false,
+            secrets,
+        })
+    }
+
+    /// Check whether the keys of the object at `path` should be obfuscated.
+    pub fn obfuscates_keys_at(&self, path: &[PathSegment]) -> bool {
+        self.obfuscate_all_keys || self.key_patterns.iter().any(|p| p.matches(path))
+    }
+
+    /// Return the action of the first rule matching `path`.
+    pub fn action_for(&self, path: &[PathSegment]) -> Option<&Action> {
+        self.rules
+            .iter()
+            .find(|rule| rule.pattern.matches(path))
+            .map(|rule| &rule.action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(segments: &[&str]) -> Vec<PathSegment> {
+        segments
+            .iter()
+            .map(|s| match s.parse() {
+                Ok(i) => PathSegment::Index(i),
+                Err(_) => PathSegment::Key(s.to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_path_pattern_parse() {
+        let pattern = PathPattern::parse("$.items[*].vault['the name']").unwrap();
+        assert_eq!(
+            pattern.segments,
+            vec![
+                PatternSegment::Key("items".to_string()),
+                PatternSegment::AnyIndex,
+                PatternSegment::Key("vault".to_string()),
+                PatternSegment::Key("the name".to_string()),
+            ]
+        );
+        assert_eq!(
+            PathPattern::parse("**.password").unwrap().segments,
+            vec![
+                PatternSegment::AnyDepth,
+                PatternSegment::Key("password".to_string())
+            ]
+        );
+        assert_eq!(
+            PathPattern::parse("$..password").unwrap(),
+            PathPattern::parse("**.password").unwrap()
+        );
+        assert!(PathPattern::parse("$.items[").is_err());
+        assert!(PathPattern::parse("$.items[x]").is_err());
+        assert!(PathPattern::parse("$.a..").is_ok());
+        assert!(PathPattern::parse("$.a.").is_err());
+    }
+
+    #[test]
+    fn test_path_pattern_matches() {
+        let pattern = PathPattern::parse("$.items[*].vault.name").unwrap();
+        assert!(pattern.matches(&path(&["items", "0", "vault", "name"])));
+        assert!(!pattern.matches(&path(&["items", "vault", "name"])));
+        assert!(!pattern.matches(&path(&["items", "0", "vault"])));
+
+        let anywhere = PathPattern::parse("**.password").unwrap();
+        assert!(anywhere.matches(&path(&["password"])));
+        assert!(anywhere.matches(&path(&["a", "3", "password"])));
+        assert!(!anywhere.matches(&path(&["password", "hint"])));
+
+        let star = PathPattern::parse("$.*.id").unwrap();
+        assert!(star.matches(&path(&["vault", "id"])));
+        assert!(star.matches(&path(&["2", "id"])));
+        assert!(!star.matches(&path(&["id"])));
+
+        let root = PathPattern::parse("$").unwrap();
+        assert!(root.matches(&[]));
+        assert!(!root.matches(&path(&["a"])));
+    }
+
+    #[test]
+    fn test_rules_parse() {
+        let rules = Rules::parse(
+            r#"
+rules:
+  - path: "$.category"
+    action: keep
+  - path: "**.password"
+    action: redact
+  - path: "**.token"
+    action: redact
+    placeholder: "***"
+  - path: "$.vault.name"
+    action: obfuscate
+    classifier: capitalized_word
+  - path: "**.internal"
+    action: delete
+  - path: "**.note"
+    action: "null"
+"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            rules.action_for(&path(&["category"])),
+            Some(Action::Keep)
+        ));
+        assert!(matches!(
+            rules.action_for(&path(&["a", "password"])),
+            Some(Action::Redact(p)) if p == DEFAULT_PLACEHOLDER
+        ));
+        assert!(matches!(
+            rules.action_for(&path(&["token"])),
+            Some(Action::Redact(p)) if p == "***"
+        ));
+        assert!(matches!(
+            rules.action_for(&path(&["vault", "name"])),
+            Some(Action::Obfuscate(Some(c), None)) if c.name == "capitalized_word"
+        ));
+        assert!(matches!(
+            rules.action_for(&path(&["internal"])),
+            Some(Action::Delete)
+        ));
+        assert!(matches!(
+            rules.action_for(&path(&["note"])),
+            Some(Action::Null)
+        ));
+        assert!(rules.action_for(&path(&["title"])).is_none());
+    }
+
+    #[test]
+    fn test_obfuscates_keys_at() {
+        let mut rules = Rules::parse("obfuscate_keys:\n  - $.users\n  - '**.tenants'\n").unwrap();
+        assert!(rules.obfuscates_keys_at(&path(&["users"])));
+        assert!(rules.obfuscates_keys_at(&path(&["a", "0", "tenants"])));
+        assert!(!rules.obfuscates_keys_at(&[]));
+        rules.obfuscate_all_keys = true;
+        assert!(rules.obfuscates_keys_at(&[]));
+    }
+
+    #[test]
+    fn test_rules_parse_errors() {
+        let unknown = "rules:\n  - path: $.a\n    action: obfuscate\n    classifier: nope\n";
+        assert!(matches!(
+            Rules::parse(unknown),
+            Err(RulesError::UnknownClassifier(name)) if name == "nope"
+        ));
+        let misplaced = "rules:\n  - path: $.a\n    action: keep\n    placeholder: x\n";
+        assert!(matches!(
+            Rules::parse(misplaced),
+            Err(RulesError::UnexpectedField("placeholder", _))
+        ));
+        let bad_path = "rules:\n  - path: $.a[\n    action: keep\n";
+        assert!(matches!(
+            Rules::parse(bad_path),
+            Err(RulesError::InvalidPath(_, _))
+        ));
+        assert!(matches!(
+            Rules::parse("rules:\n  - path: $.a\n    action: shred\n"),
+            Err(RulesError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_rules_parse_numbers() {
+        let rules = Rules::parse(
+            r#"
+rules:
+  - path: "**.customer_id"
+    action: obfuscate
+    numbers: remap
+  - path: "**.balance"
+    action: obfuscate
+    numbers: noise
+    noise: 0.05
+  - path: "**.age"
+    action: obfuscate
+    numbers: bucket
+    step: 10
+  - path: "**.lat"
+    action: obfuscate
+    numbers: latitude
+"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            rules.action_for(&path(&["customer_id"])),
+            Some(Action::Obfuscate(None, Some(NumberStrategy::Remap)))
+        ));
+        assert!(matches!(
+            rules.action_for(&path(&["balance"])),
+            Some(Action::Obfuscate(None, Some(NumberStrategy::Noise(n)))) if *n == 0.05
+        ));
+        assert!(matches!(
+            rules.action_for(&path(&["age"])),
+            Some(Action::Obfuscate(None, Some(NumberStrategy::Bucket(s)))) if *s == 10.0
+        ));
+        assert!(matches!(
+            rules.action_for(&path(&["lat"])),
+            Some(Action::Obfuscate(None, Some(NumberStrategy::Latitude)))
+        ));
+
+        let misplaced = "rules:\n  - path: $.a\n    action: keep\n    numbers: remap\n";
+        assert!(matches!(
+            Rules::parse(misplaced),
+            Err(RulesError::UnexpectedField("numbers", _))
+        ));
+        let step =
+            "rules:\n  - path: $.a\n    action: obfuscate\n    numbers: noise\n    step: 2\n";
+        assert!(matches!(
+            Rules::parse(step),
+            Err(RulesError::UnexpectedField("step", _))
+        ));
+        let noise =
+            "rules:\n  - path: $.a\n    action: obfuscate\n    numbers: noise\n    noise: 2\n";
+        assert!(matches!(
+            Rules::parse(noise),
+            Err(RulesError::InvalidValue("noise", _))
+        ));
+    }
+
+    #[test]
+    fn test_rules_parse_secrets() {
+        let rules = Rules::parse("secrets:\n  jwt: redact\n  high_entropy: off\n").unwrap();
+        assert_eq!(rules.secrets.action("jwt"), SecretAction::Redact);
+        assert_eq!(rules.secrets.action("high_entropy"), SecretAction::Off);
+        assert_eq!(rules.secrets.action("aws_access_key"), SecretAction::Fake);
+        assert!(matches!(
+            Rules::parse("secrets:\n  passwords: redact\n"),
+            Err(RulesError::UnknownSecretType(name)) if name == "passwords"
+        ));
+        assert!(matches!(
+            Rules::parse("secrets:\n  jwt: shred\n"),
+            Err(RulesError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,1267 @@
+//! Token-level JSON pipeline. Documents are read, obfuscated and written one
+//! scalar at a time, so memory does not grow with the size of an array or
+//! object. Objects whose keys are obfuscated only remember their new keys to
+//! detect collisions.
+
+use serde_json::{Number, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::classifiers::Classifier;
+use crate::error::OnError;
+use crate::numbers::NumberStrategy;
+use crate::rules::{Action, PathSegment, Rules};
+use crate::{obfuscate_key, walk};
+
+/// Deepest nesting accepted, matching `serde_json`'s own limit.
+const MAX_DEPTH: usize = 128;
+
+/// Buffered output is handed to the destination once it reaches this size.
+const FLUSH_AT: usize = 8 * 1024;
+
+/// Error raised while streaming a JSON document.
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    Syntax(SyntaxError),
+}
+
+/// Malformed JSON, located at the last byte read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: &'static str,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {} column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Io(e) => write!(f, "{}", e),
+            StreamError::Syntax(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for StreamError {
+    fn from(e: io::Error) -> Self {
+        StreamError::Io(e)
+    }
+}
+
+type Result<T> = std::result::Result<T, StreamError>;
+
+/// Obfuscate every JSON value in `reader` and write it to `writer`. By default
+/// each value is written in the layout of `serde_json::to_writer_pretty`,
+/// followed by a newline. With `preserve_format` the input bytes are copied
+/// through and only obfuscated values and keys are rewritten.
+///
+/// With [`OnError::Fail`] output is written as it is produced. The other
+/// policies hold each top-level value until it has been read completely, so
+/// a malformed one can be dropped or copied through; reading then resumes on
+/// the line after the error. A top-level array is held one element at a
+/// time instead, and recovery resumes at the next element.
+pub fn obfuscate_json<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    rules: &Rules,
+    preserve_format: bool,
+    on_error: OnError,
+) -> Result<()> {
+    if preserve_format {
+        let reader = Reader::recording(reader);
+        run(reader, PreservingWriter::default(), writer, rules, on_error)
+    } else {
+        let reader = Reader::new(reader);
+        run(reader, PrettyWriter::default(), writer, rules, on_error)
+    }
+}
+
+/// Obfuscate one line of JSON Lines input holding exactly one value, counted
+/// as line `number` in error messages. Returns the compact output line, or
+/// `None` when a rule deleted the record. Nothing is returned on error, so a
+/// malformed line is never half written.
+pub fn obfuscate_json_line(
+    line: &[u8],
+    number: usize,
+    rules: &Rules,
+    preserve_format: bool,
+) -> Result<Option<Vec<u8>>> {
+    let mut reader = match preserve_format {
+        true => Reader::recording(line),
+        false => Reader::new(line),
+    };
+    reader.line = number;
+    if preserve_format {
+        single_value(reader, PreservingWriter::default(), rules)
+    } else {
+        single_value(reader, CompactWriter::default(), rules)
+    }
+}
+
+fn single_value<R: BufRead, O: Output>(
+    reader: Reader<R>,
+    output: O,
+    rules: &Rules,
+) -> Result<Option<Vec<u8>>> {
+    let mut stream = JsonStream {
+        reader,
+        output,
+        sink: io::sink(),
+        streaming: false,
+        rules,
+        path: Vec::new(),
+    };
+    let kept = stream.value(Prefix::default(), None, None)?;
+    if stream.reader.skip_whitespace()?.is_some() {
+        stream.reader.bump()?;
+        return stream.reader.error("trailing characters");
+    }
+    let trailing = stream.reader.take();
+    stream.output.finish(&trailing);
+    Ok(kept.then(|| std::mem::take(stream.output.buffer())))
+}
+
+fn run<R: BufRead, O: Output, W: Write>(
+    reader: Reader<R>,
+    output: O,
+    sink: W,
+    rules: &Rules,
+    on_error: OnError,
+) -> Result<()> {
+    let mut stream = JsonStream {
+        reader,
+        output,
+        sink,
+        streaming: on_error == OnError::Fail,
+        rules,
+        path: Vec::new(),
+    };
+    loop {
+        if on_error != OnError::Fail {
+            stream.reader.capture = Some(Vec::new());
+        }
+        match stream.reader.skip_whitespace()? {
+            None => break,
+            Some(b'[') if on_error != OnError::Fail && rules.action_for(&[]).is_none() => {
+                stream.elements(on_error)?;
+                stream.drain()?;
+                continue;
+            }
+            Some(_) => {}
+        }
+        match stream.document() {
+            Ok(()) => stream.drain()?,
+            Err(StreamError::Syntax(e)) if on_error != OnError::Fail => {
+                stream.reader.skip_line()?;
+                stream.reader.reset();
+                stream.output.reset();
+                stream.path.clear();
+                let raw = stream.reader.capture.take().unwrap_or_default();
+                let start = raw.iter().take_while(|b| b.is_ascii_whitespace()).count();
+                stream.output.finish(&raw[..start]);
+                stream.drain()?;
+                if on_error == OnError::Passthrough {
+                    eprintln!("pipefog: passing invalid JSON through: {}", e);
+                    stream.sink.write_all(&raw[start..])?;
+                } else {
+                    eprintln!("pipefog: skipping invalid JSON: {}", e);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let trailing = stream.reader.take();
+    stream.output.finish(&trailing);
+    stream.drain()?;
+    Ok(())
+}
+
+/// What precedes a value in its container: the input bytes since the previous
+/// token (whitespace, `,` and, for object members, the key and `:`) and the
+/// member name to write.
+#[derive(Default)]
+struct Prefix {
+    raw: Vec<u8>,
+    key: Option<String>,
+}
+
+/// Object member name together with the input bytes around it.
+struct Member {
+    name: String,
+    leading: Vec<u8>,
+    raw_name: Vec<u8>,
+    separator: Vec<u8>,
+}
+
+impl Member {
+    /// Prefix writing the member as `name`, keeping the original spelling of
+    /// the key when it is unchanged.
+    fn prefix(self, name: String) -> Prefix {
+        let mut raw = self.leading;
+        if name == self.name {
+            raw.extend(self.raw_name);
+        } else {
+            raw.extend(Value::String(name.clone()).to_string().bytes());
+        }
+        raw.extend(self.separator);
+        Prefix {
+            raw,
+            key: Some(name),
+        }
+    }
+}
+
+struct JsonStream<'a, R, O, W> {
+    reader: Reader<R>,
+    output: O,
+    /// Destination of the buffered output.
+    sink: W,
+    /// Whether output is handed to `sink` while a value is still being read.
+    streaming: bool,
+    rules: &'a Rules,
+    path: Vec<PathSegment>,
+}
+
+impl<R: BufRead, O: Output, W: Write> JsonStream<'_, R, O, W> {
+    /// Stream one top-level value.
+    fn document(&mut self) -> Result<()> {
+        let kept = self.value(Prefix::default(), None, None)?;
+        self.output.end_document(kept);
+        Ok(())
+    }
+
+    /// Stream a top-level array one element at a time. A malformed element
+    /// is dropped or copied through as set by `on_error`, and reading resumes
+    /// after the `,` or `]` that ends it.
+    fn elements(&mut self, on_error: OnError) -> Result<()> {
+        let leading = self.reader.take();
+        self.reader.capture = None;
+        self.reader.open(b'[')?;
+        self.reader.take();
+        self.output.prefix(&Prefix {
+            raw: leading,
+            key: None,
+        });
+        self.output.begin(b'[');
+        let mut index = 0;
+        let mut first = true;
+        // Input after a malformed element up to and including its `,`.
+        let mut carry = Vec::new();
+        loop {
+            self.drain()?;
+            let open = self.output.open().clone();
+            let comma = !first || !carry.is_empty();
+            self.reader.capture = Some(std::mem::take(&mut carry));
+            let e = match self.element(index, &mut first) {
+                Ok(true) => {
+                    index += 1;
+                    continue;
+                }
+                Ok(false) => break,
+                Err(StreamError::Syntax(e)) => e,
+                Err(e) => return Err(e),
+            };
+            index += 1;
+            self.path.clear();
+            *self.output.open() = open;
+            self.output.buffer().clear();
+            let (raw, boundary) = self.reader.resync(comma)?;
+            let lead = raw.iter().take_while(|b| b.is_ascii_whitespace()).count();
+            let start = match raw.get(lead) {
+                Some(b',') if comma => {
+                    lead + 1
+                        + raw[lead + 1..]
+                            .iter()
+                            .take_while(|b| b.is_ascii_whitespace())
+                            .count()
+                }
+                _ => lead,
+            };
+            let trailing = raw[start..]
+                .iter()
+                .rev()
+                .take_while(|b| b.is_ascii_whitespace())
+                .count();
+            let end = raw.len() - trailing;
+            if on_error == OnError::Passthrough {
+                eprintln!("pipefog: passing invalid JSON through: {}", e);
+                self.output.prefix(&Prefix {
+                    raw: raw[..start].to_vec(),
+                    key: None,
+                });
+                self.output.buffer().extend_from_slice(&raw[start..end]);
+            } else {
+                eprintln!("pipefog: skipping invalid JSON: {}", e);
+            }
+            // Hand the bytes after the element to the next one, or to the
+            // closing bracket.
+            self.reader.take();
+            if let (Some(record), Some(b)) = (&mut self.reader.record, boundary) {
+                record.extend_from_slice(&raw[end..]);
+                record.push(b);
+            }
+            match boundary {
+                Some(b',') => {
+                    carry.extend_from_slice(&raw[end..]);
+                    carry.push(b',');
+                    self.reader.depth = 1;
+                    first = true;
+                }
+                _ => {
+                    self.reader.depth = 0;
+                    break;
+                }
+            }
+        }
+        self.reader.capture = None;
+        let closing = self.reader.take();
+        self.output.end(&closing, b']');
+        self.output.end_document(true);
+        Ok(())
+    }
+
+    /// Stream the element at `index` of a top-level array, or return `false`
+    /// at its closing bracket.
+    fn element(&mut self, index: usize, first: &mut bool) -> Result<bool> {
+        if !self.reader.next_element(b']', first)? {
+            return Ok(false);
+        }
+        let element = Prefix {
+            raw: self.reader.take(),
+            key: None,
+        };
+        self.path.push(PathSegment::Index(index));
+        self.value(element, None, None)?;
+        self.path.pop();
+        Ok(true)
+    }
+
+    /// Hand the buffered output to the destination.
+    fn drain(&mut self) -> io::Result<()> {
+        let buffer = self.output.buffer();
+        self.sink.write_all(buffer)?;
+        buffer.clear();
+        Ok(())
+    }
+
+    /// Called after each scalar: drain the output once enough has piled up.
+    fn written(&mut self) -> io::Result<()> {
+        if self.streaming && self.output.buffer().len() >= FLUSH_AT {
+            self.drain()?;
+        }
+        Ok(())
+    }
+
+    /// Stream the value at the current path, applying its rule like
+    /// [`crate::visit`]. `prefix` is written before the value when it is kept.
+    /// Returns `false` when a rule deleted the value.
+    fn value(
+        &mut self,
+        mut prefix: Prefix,
+        classifier: Option<&'static Classifier>,
+        numbers: Option<NumberStrategy>,
+    ) -> Result<bool> {
+        let next = self.reader.skip_whitespace()?;
+        prefix.raw.extend(self.reader.take());
+        let (classifier, numbers) = match self.rules.action_for(&self.path) {
+            None => (classifier, numbers),
+            Some(Action::Obfuscate(named, strategy)) => (*named, *strategy),
+            Some(Action::Keep) => {
+                self.copy(prefix, true)?;
+                return Ok(true);
+            }
+            Some(Action::Redact(placeholder)) => {
+                let placeholder = Value::String(placeholder.clone());
+                return self.replace(prefix, &placeholder);
+            }
+            Some(Action::Null) => return self.replace(prefix, &Value::Null),
+            Some(Action::Delete) => {
+                self.copy(prefix, false)?;
+                return Ok(false);
+            }
+        };
+        match next {
+            Some(b'[') => self.array(prefix, classifier, numbers)?,
+            Some(b'{') => self.object(prefix, classifier, numbers)?,
+            _ => {
+                let original = self.reader.read_scalar()?;
+                let raw = self.reader.take();
+                let mut value = original.clone();
+                walk(&mut value, self.rules, &mut self.path, classifier, numbers);
+                self.output.prefix(&prefix);
+                self.output.scalar(&raw, &value, value != original);
+                self.written()?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Skip the value and write `replacement` in its place.
+    fn replace(&mut self, prefix: Prefix, replacement: &Value) -> Result<bool> {
+        self.copy(Prefix::default(), false)?;
+        self.output.prefix(&prefix);
+        self.output.scalar(&[], replacement, true);
+        self.written()?;
+        Ok(true)
+    }
+
+    fn array(
+        &mut self,
+        prefix: Prefix,
+        classifier: Option<&'static Classifier>,
+        numbers: Option<NumberStrategy>,
+    ) -> Result<()> {
+        self.reader.open(b'[')?;
+        self.reader.take();
+        self.output.prefix(&prefix);
+        self.output.begin(b'[');
+        let mut index = 0;
+        let mut first = true;
+        while self.reader.next_element(b']', &mut first)? {
+            let element = Prefix {
+                raw: self.reader.take(),
+                key: None,
+            };
+            self.path.push(PathSegment::Index(index));
+            index += 1;
+            self.value(element, classifier, numbers)?;
+            self.path.pop();
+        }
+        let closing = self.reader.take();
+        self.output.end(&closing, b']');
+        Ok(())
+    }
+
+    fn object(
+        &mut self,
+        prefix: Prefix,
+        classifier: Option<&'static Classifier>,
+        numbers: Option<NumberStrategy>,
+    ) -> Result<()> {
+        self.reader.open(b'{')?;
+        self.reader.take();
+        self.output.prefix(&prefix);
+        self.output.begin(b'{');
+        let mut renamed = self.rules.obfuscates_keys_at(&self.path).then(HashSet::new);
+        let mut first = true;
+        while self.reader.next_element(b'}', &mut first)? {
+            let member = self.member()?;
+            let key = member.name.clone();
+            let name = match &renamed {
+                Some(taken) => obfuscate_key(&key, |k| taken.contains(k)),
+                None => key.clone(),
+            };
+            self.path.push(PathSegment::Key(key));
+            let kept = self.value(member.prefix(name.clone()), classifier, numbers)?;
+            self.path.pop();
+            if let (true, Some(taken)) = (kept, &mut renamed) {
+                taken.insert(name);
+            }
+        }
+        let closing = self.reader.take();
+        self.output.end(&closing, b'}');
+        Ok(())
+    }
+
+    /// Read an object member name up to and including its `:`.
+    fn member(&mut self) -> Result<Member> {
+        match self.reader.skip_whitespace()? {
+            Some(b'"') => {}
+            Some(_) => {
+                self.reader.bump()?;
+                return self.reader.error("key must be a string");
+            }
+            None => return self.reader.error("EOF while parsing an object"),
+        }
+        let leading = self.reader.take();
+        let name = self.reader.read_string()?;
+        let raw_name = self.reader.take();
+        self.reader.expect(b':', "expected `:`")?;
+        Ok(Member {
+            name,
+            leading,
+            raw_name,
+            separator: self.reader.take(),
+        })
+    }
+
+    /// Stream a value through unchanged, or discard it when `write` is false.
+    fn copy(&mut self, mut prefix: Prefix, write: bool) -> Result<()> {
+        let next = self.reader.skip_whitespace()?;
+        prefix.raw.extend(self.reader.take());
+        match next {
+            Some(open @ (b'[' | b'{')) => {
+                let close = if open == b'[' { b']' } else { b'}' };
+                self.reader.open(open)?;
+                self.reader.take();
+                if write {
+                    self.output.prefix(&prefix);
+                    self.output.begin(open);
+                }
+                let mut first = true;
+                while self.reader.next_element(close, &mut first)? {
+                    let element = match open {
+                        b'{' => {
+                            let member = self.member()?;
+                            let name = member.name.clone();
+                            member.prefix(name)
+                        }
+                        _ => Prefix {
+                            raw: self.reader.take(),
+                            key: None,
+                        },
+                    };
+                    self.copy(element, write)?;
+                }
+                let closing = self.reader.take();
+                if write {
+                    self.output.end(&closing, close);
+                }
+            }
+            _ => {
+                let value = self.reader.read_scalar()?;
+                let raw = self.reader.take();
+                if write {
+                    self.output.prefix(&prefix);
+                    self.output.scalar(&raw, &value, false);
+                    self.written()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Byte reader that keeps track of the position for error messages and, when
+/// recording, of the bytes consumed since the last [`Reader::take`].
+struct Reader<R> {
+    inner: R,
+    line: usize,
+    column: usize,
+    depth: usize,
+    record: Option<Vec<u8>>,
+    /// Every byte of the current top-level value, or of the current element
+    /// of a top-level array, kept to pass it through when it turns out to be
+    /// malformed.
+    capture: Option<Vec<u8>>,
+}
+
+impl<R: BufRead> Reader<R> {
+    fn new(inner: R) -> Self {
+        Reader {
+            inner,
+            line: 1,
+            column: 0,
+            depth: 0,
+            record: None,
+            capture: None,
+        }
+    }
+
+    fn recording(inner: R) -> Self {
+        Reader {
+            record: Some(Vec::new()),
+            ..Reader::new(inner)
+        }
+    }
+
+    /// Bytes consumed since the previous call, empty when not recording.
+    fn take(&mut self) -> Vec<u8> {
+        self.record.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn error<T>(&self, message: &'static str) -> Result<T> {
+        Err(StreamError::Syntax(SyntaxError {
+            message,
+            line: self.line,
+            column: self.column,
+        }))
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        loop {
+            match self.inner.fill_buf() {
+                Ok(buf) => return Ok(buf.first().copied()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn bump(&mut self) -> Result<Option<u8>> {
+        let byte = self.peek()?;
+        if let Some(b) = byte {
+            self.inner.consume(1);
+            if let Some(record) = &mut self.record {
+                record.push(b);
+            }
+            if let Some(capture) = &mut self.capture {
+                capture.push(b);
+            }
+            if b == b'\n' {
+                self.line += 1;
+                self.column = 0;
+            } else {
+                self.column += 1;
+            }
+        }
+        Ok(byte)
+    }
+
+    /// Consume the rest of the current line, including its line break.
+    fn skip_line(&mut self) -> Result<()> {
+        if self.column == 0 {
+            return Ok(());
+        }
+        while let Some(b) = self.bump()? {
+            if b == b'\n' {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read to the end of a malformed array element: rescan its captured
+    /// bytes, then the input, for the `,` or `]` that closes it, skipping
+    /// strings and nested brackets. `comma` is set when the element should
+    /// start with a separator. Returns the element's bytes and the consumed
+    /// boundary, `None` at the end of the input.
+    fn resync(&mut self, comma: bool) -> Result<(Vec<u8>, Option<u8>)> {
+        let mut raw = self.capture.take().unwrap_or_default();
+        self.take();
+        let mut scan = Resync {
+            comma,
+            ..Resync::default()
+        };
+        if let Some(at) = raw.iter().position(|&b| scan.boundary(b)) {
+            let boundary = raw[at];
+            raw.truncate(at);
+            return Ok((raw, Some(boundary)));
+        }
+        while let Some(b) = self.bump()? {
+            if scan.boundary(b) {
+                return Ok((raw, Some(b)));
+            }
+            raw.push(b);
+        }
+        Ok((raw, None))
+    }
+
+    /// Forget the containers and recorded bytes of an abandoned value.
+    fn reset(&mut self) {
+        self.depth = 0;
+        self.take();
+    }
+
+    /// Skip whitespace and return the next byte without consuming it.
+    fn skip_whitespace(&mut self) -> Result<Option<u8>> {
+        while let Some(b) = self.peek()? {
+            if !matches!(b, b' ' | b'\t' | b'\n' | b'\r') {
+                return Ok(Some(b));
+            }
+            self.bump()?;
+        }
+        Ok(None)
+    }
+
+    fn expect(&mut self, byte: u8, message: &'static str) -> Result<()> {
+        self.skip_whitespace()?;
+        match self.bump()? {
+            Some(b) if b == byte => Ok(()),
+            Some(_) => self.error(message),
+            None => self.error("EOF while parsing a value"),
+        }
+    }
+
+    /// Consume the opening bracket of an array or object.
+    fn open(&mut self, bracket: u8) -> Result<()> {
+        self.expect(bracket, "expected value")?;
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.error("recursion limit exceeded");
+        }
+        Ok(())
+    }
+
+    /// Advance to the next element of the array or object closed by `close`.
+    /// `first` stays set until the first element has been reached. Returns
+    /// `false` once the closing bracket has been consumed.
+    fn next_element(&mut self, close: u8, first: &mut bool) -> Result<bool> {
+        match self.skip_whitespace()? {
+            Some(b) if b == close => {
+                self.bump()?;
+                self.depth -= 1;
+                Ok(false)
+            }
+            Some(b',') if !*first => {
+                self.bump()?;
+                Ok(true)
+            }
+            Some(_) if *first => {
+                *first = false;
+                Ok(true)
+            }
+            Some(_) if close == b']' => self.error("expected `,` or `]`"),
+            Some(_) => self.error("expected `,` or `}`"),
+            None => self.error("EOF while parsing a value"),
+        }
+    }
+
+    /// Read a string, number or literal.
+    fn read_scalar(&mut self) -> Result<Value> {
+        match self.skip_whitespace()? {
+            Some(b'"') => Ok(Value::String(self.read_string()?)),
+            Some(b't') => self.read_literal("true", Value::Bool(true)),
+            Some(b'f') => self.read_literal("false", Value::Bool(false)),
+            Some(b'n') => self.read_literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.read_number(),
+            Some(_) => {
+                self.bump()?;
+                self.error("expected value")
+            }
+            None => self.error("EOF while parsing a value"),
+        }
+    }
+
+    fn read_literal(&mut self, literal: &str, value: Value) -> Result<Value> {
+        for expected in literal.bytes() {
+            match self.bump()? {
+                Some(b) if b == expected => {}
+                Some(_) => return self.error("expected ident"),
+                None => return self.error("EOF while parsing a value"),
+            }
+        }
+        Ok(value)
+    }
+
+    fn read_number(&mut self) -> Result<Value> {
+        let mut raw = String::new();
+        while let Some(b) = self.peek()? {
+            if !matches!(b, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') {
+                break;
+            }
+            raw.push(b as char);
+            self.bump()?;
+        }
+        match serde_json::from_str::<Number>(&raw) {
+            Ok(number) => Ok(Value::Number(number)),
+            Err(_) => self.error("invalid number"),
+        }
+    }
+
+    /// Read a string literal, starting at its opening quote.
+    fn read_string(&mut self) -> Result<String> {
+        self.bump()?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.bump()? {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let c = match self.bump()? {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.read_escaped_char()?,
+                        Some(_) => return self.error("invalid escape"),
+                        None => return self.error("EOF while parsing a string"),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(b) if b < 0x20 => {
+                    return self
+                        .error("control character (\\u0000-\\u001F) found while parsing a string")
+                }
+                Some(b) => bytes.push(b),
+                None => return self.error("EOF while parsing a string"),
+            }
+        }
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok(s),
+            Err(_) => self.error("invalid unicode code point"),
+        }
+    }
+
+    /// Decode the code point after `\u`, joining UTF-16 surrogate pairs.
+    fn read_escaped_char(&mut self) -> Result<char> {
+        let high = self.read_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if self.bump()? != Some(b'\\') || self.bump()? != Some(b'u') {
+                return self.error("unexpected end of hex escape");
+            }
+            let low = self.read_hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return self.error("lone leading surrogate in hex escape");
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => self.error("invalid unicode code point"),
+        }
+    }
+
+    fn read_hex4(&mut self) -> Result<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = match self.bump()? {
+                Some(b) => (b as char).to_digit(16),
+                None => return self.error("EOF while parsing a string"),
+            };
+            match digit {
+                Some(d) => code = code * 16 + d,
+                None => return self.error("invalid escape"),
+            }
+        }
+        Ok(code)
+    }
+}
+
+/// Scanner state while looking for the end of a malformed array element.
+#[derive(Default)]
+struct Resync {
+    comma: bool,
+    started: bool,
+    depth: usize,
+    string: bool,
+    escape: bool,
+}
+
+impl Resync {
+    /// Feed the next byte and return whether it ends the element.
+    fn boundary(&mut self, b: u8) -> bool {
+        if self.string {
+            match b {
+                _ if self.escape => self.escape = false,
+                b'\\' => self.escape = true,
+                b'"' => self.string = false,
+                _ => {}
+            }
+            return false;
+        }
+        if !self.started {
+            if b.is_ascii_whitespace() {
+                return false;
+            }
+            self.started = true;
+            if b == b',' && self.comma {
+                return false;
+            }
+        }
+        match b {
+            b'"' => self.string = true,
+            b'[' | b'{' => self.depth += 1,
+            b']' | b'}' if self.depth > 0 => self.depth -= 1,
+            b',' | b']' if self.depth == 0 => return true,
+            _ => {}
+        }
+        false
+    }
+}
+
+/// Destination of the streamed tokens. Output is collected in memory and
+/// handed on by [`JsonStream::drain`].
+trait Output {
+    /// Start a kept value in the current container.
+    fn prefix(&mut self, prefix: &Prefix);
+    fn begin(&mut self, bracket: u8);
+    /// Close the current container. `raw` holds the input bytes up to and
+    /// including the closing bracket.
+    fn end(&mut self, raw: &[u8], bracket: u8);
+    /// Write `value`, read from the input as `raw` and `changed` if a rule or
+    /// classifier rewrote it.
+    fn scalar(&mut self, raw: &[u8], value: &Value, changed: bool);
+    /// Finish a top-level value, `kept` unless a rule deleted it.
+    fn end_document(&mut self, _kept: bool) {}
+    /// Write what is left of the input after the last value.
+    fn finish(&mut self, _raw: &[u8]) {}
+    /// Output not yet handed on.
+    fn buffer(&mut self) -> &mut Vec<u8>;
+    /// Whether each open container has written a value yet.
+    fn open(&mut self) -> &mut Vec<bool>;
+    /// Drop the output of a value abandoned halfway.
+    fn reset(&mut self);
+}
+
+fn write_json(out: &mut Vec<u8>, value: &impl serde::Serialize) {
+    serde_json::to_writer(out, value).expect("serialise json to memory");
+}
+
+/// Writer producing the layout of `serde_json::to_writer_pretty`.
+#[derive(Default)]
+struct PrettyWriter {
+    out: Vec<u8>,
+    /// Whether each open container has written a value yet.
+    open: Vec<bool>,
+}
+
+impl PrettyWriter {
+    fn indent(&mut self, depth: usize) {
+        for _ in 0..depth {
+            self.out.extend_from_slice(b"  ");
+        }
+    }
+}
+
+impl Output for PrettyWriter {
+    fn prefix(&mut self, prefix: &Prefix) {
+        let depth = self.open.len();
+        if let Some(has_value) = self.open.last_mut() {
+            let separator: &[u8] = if *has_value { b",\n" } else { b"\n" };
+            *has_value = true;
+            self.out.extend_from_slice(separator);
+            self.indent(depth);
+        }
+        if let Some(key) = &prefix.key {
+            write_json(&mut self.out, key);
+            self.out.extend_from_slice(b": ");
+        }
+    }
+
+    fn begin(&mut self, bracket: u8) {
+        self.open.push(false);
+        self.out.push(bracket);
+    }
+
+    fn end(&mut self, _raw: &[u8], bracket: u8) {
+        if self.open.pop() == Some(true) {
+            self.out.push(b'\n');
+            self.indent(self.open.len());
+        }
+        self.out.push(bracket);
+    }
+
+    fn scalar(&mut self, _raw: &[u8], value: &Value, _changed: bool) {
+        write_json(&mut self.out, value);
+    }
+
+    fn end_document(&mut self, kept: bool) {
+        if kept {
+            self.out.push(b'\n');
+        }
+    }
+
+    fn buffer(&mut self) -> &mut Vec<u8> {
+        &mut self.out
+    }
+
+    fn open(&mut self) -> &mut Vec<bool> {
+        &mut self.open
+    }
+
+    fn reset(&mut self) {
+        self.out.clear();
+        self.open.clear();
+    }
+}
+
+/// Writer producing compact JSON on a single line.
+#[derive(Default)]
+struct CompactWriter {
+    out: Vec<u8>,
+    /// Whether each open container has written a value yet.
+    open: Vec<bool>,
+}
+
+impl Output for CompactWriter {
+    fn prefix(&mut self, prefix: &Prefix) {
+        if let Some(has_value) = self.open.last_mut() {
+            if *has_value {
+                self.out.push(b',');
+            }
+            *has_value = true;
+        }
+        if let Some(key) = &prefix.key {
+            write_json(&mut self.out, key);
+            self.out.push(b':');
+        }
+    }
+
+    fn begin(&mut self, bracket: u8) {
+        self.open.push(false);
+        self.out.push(bracket);
+    }
+
+    fn end(&mut self, _raw: &[u8], bracket: u8) {
+        self.open.pop();
+        self.out.push(bracket);
+    }
+
+    fn scalar(&mut self, _raw: &[u8], value: &Value, _changed: bool) {
+        write_json(&mut self.out, value);
+    }
+
+    fn buffer(&mut self) -> &mut Vec<u8> {
+        &mut self.out
+    }
+
+    fn open(&mut self) -> &mut Vec<bool> {
+        &mut self.open
+    }
+
+    fn reset(&mut self) {
+        self.out.clear();
+        self.open.clear();
+    }
+}
+
+/// Writer copying the input bytes, so key order, whitespace and the spelling
+/// of numbers and escapes survive. Only changed values are serialised anew.
+#[derive(Default)]
+struct PreservingWriter {
+    out: Vec<u8>,
+    /// Whether each open container has written a value yet.
+    open: Vec<bool>,
+}
+
+impl Output for PreservingWriter {
+    fn prefix(&mut self, prefix: &Prefix) {
+        let mut raw = &prefix.raw[..];
+        if let Some(has_value) = self.open.last_mut() {
+            // Drop the comma of a value whose predecessors were all deleted.
+            if !*has_value {
+                if let Some(comma) = raw.iter().position(|b| !b.is_ascii_whitespace()) {
+                    if raw[comma] == b',' {
+                        self.out.extend_from_slice(&raw[..comma]);
+                        raw = &raw[comma + 1..];
+                    }
+                }
+            }
+            *has_value = true;
+        }
+        self.out.extend_from_slice(raw);
+    }
+
+    fn begin(&mut self, bracket: u8) {
+        self.open.push(false);
+        self.out.push(bracket);
+    }
+
+    fn end(&mut self, raw: &[u8], _bracket: u8) {
+        self.open.pop();
+        self.out.extend_from_slice(raw);
+    }
+
+    fn scalar(&mut self, raw: &[u8], value: &Value, changed: bool) {
+        if changed {
+            write_json(&mut self.out, value);
+        } else {
+            self.out.extend_from_slice(raw);
+        }
+    }
+
+    fn finish(&mut self, raw: &[u8]) {
+        self.out.extend_from_slice(raw);
+    }
+
+    fn buffer(&mut self) -> &mut Vec<u8> {
+        &mut self.out
+    }
+
+    fn open(&mut self) -> &mut Vec<bool> {
+        &mut self.open
+    }
+
+    fn reset(&mut self) {
+        self.out.clear();
+        self.open.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_strings;
+
+    fn stream(input: &str, rules: &Rules) -> String {
+        let mut out = Vec::new();
+        obfuscate_json(input.as_bytes(), &mut out, rules, false, OnError::Fail).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn preserve(input: &str, rules: &Rules) -> String {
+        let mut out = Vec::new();
+        obfuscate_json(input.as_bytes(), &mut out, rules, true, OnError::Fail).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_obfuscate_json_matches_whole_value_walk() {
+        let rules = Rules::parse(
+            "rules:\n  - path: $[*].kept\n    action: keep\n  - path: $[*].gone\n    action: delete\n  - path: \"**.secret\"\n    action: redact\n  - path: $[*].cleared\n    action: \"null\"\n  - path: $[*].count\n    action: obfuscate\n    numbers: remap\nobfuscate_keys:\n  - $[*].users\n",
+        )
+        .unwrap();
+        let input = r#"[
+            {"cleared": {"a": 1}, "count": 1234, "empty": [], "gone": [1, 2],
+             "kept": {"name": "Alice"}, "name": "Alice",
+             "nested": [[null, true, -1.5e3], "Word"], "none": {}, "secret": "hunter2",
+             "users": {"alice": {"role": "admin"}}},
+            "lowercase"
+        ]
+        7 "tail""#;
+
+        let mut expected = String::new();
+        for value in serde_json::Deserializer::from_str(input).into_iter::<Value>() {
+            let mut value = value.unwrap();
+            assert!(hash_strings(&mut value, &rules));
+            expected.push_str(&serde_json::to_string_pretty(&value).unwrap());
+            expected.push('\n');
+        }
+
+        assert_eq!(stream(input, &rules), expected);
+    }
+
+    #[test]
+    fn test_obfuscate_json_keeps_key_order_and_escapes() {
+        let rules = Rules::parse("rules:\n  - path: \"**\"\n    action: keep\n").unwrap();
+        let out = stream(r#"{"b": "line\nbreak", "a": "ü😀\/"}"#, &rules);
+        assert_eq!(out, "{\n  \"b\": \"line\\nbreak\",\n  \"a\": \"ü😀/\"\n}\n");
+    }
+
+    #[test]
+    fn test_obfuscate_json_deleted_document() {
+        let rules = Rules::parse("rules:\n  - path: $\n    action: delete\n").unwrap();
+        assert_eq!(stream("[1, 2] {\"a\": 3}", &rules), "");
+    }
+
+    #[test]
+    fn test_obfuscate_json_reports_position() {
+        let cases = [
+            ("[1, 2,", "EOF while parsing a value at line 1 column 6"),
+            ("[1,\n 2 3]", "expected `,` or `]` at line 2 column 3"),
+            ("{\"a\" 1}", "expected `:` at line 1 column 6"),
+            ("[1,]", "expected value at line 1 column 4"),
+            ("\"open", "EOF while parsing a string at line 1 column 5"),
+        ];
+        for (input, message) in cases {
+            let err = obfuscate_json(
+                input.as_bytes(),
+                io::sink(),
+                &Rules::default(),
+                false,
+                OnError::Fail,
+            )
+            .unwrap_err();
+            assert_eq!(err.to_string(), message, "{}", input);
+        }
+        let deep = "[".repeat(MAX_DEPTH + 1);
+        let err = obfuscate_json(
+            deep.as_bytes(),
+            io::sink(),
+            &Rules::default(),
+            false,
+            OnError::Fail,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("recursion limit exceeded"));
+    }
+
+    #[test]
+    fn test_obfuscate_json_preserve_format() {
+        let rules = Rules::parse(
+            "rules:\n  - path: $.gone\n    action: delete\n  - path: $.first\n    action: delete\n  - path: $.list[0]\n    action: delete\n  - path: $.secret\n    action: redact\n  - path: $.t\n    action: keep\n",
+        )
+        .unwrap();
+        let input = "{ \"first\": 0, \"b\" :1.0,\"a\": [ 1e3, -0 ],\n  \"gone\": {\"x\": \"y\"},\n  \"list\": [\"x\", true],\n  \"secret\": \"s\",\n  \"t\": \"\\u0041\", \"name\": \"Word\" }\n\n7 ";
+
+        let out = preserve(input, &rules);
+
+        assert_eq!(
+            out,
+            "{ \"b\" :1.0,\"a\": [ 1e3, -0 ],\n  \"list\": [ true],\n  \"secret\": \"[REDACTED]\",\n  \"t\": \"\\u0041\", \"name\": \"Than\" }\n\n7 "
+        );
+    }
+
+    #[test]
+    fn test_obfuscate_json_preserve_format_renames_keys() {
+        let rules = Rules::parse("obfuscate_keys:\n  - $.users\n").unwrap();
+        let out = preserve("{\"users\": {\"alice\" : {\"role\": 1}}}", &rules);
+        assert_eq!(out, "{\"users\": {\"upwit\" : {\"role\": 1}}}");
+    }
+
+    #[test]
+    fn test_obfuscate_json_recovers_on_next_line() {
+        let input = "{\"a\": \"test\"}\n{\"t\": \"test\",\n\"x\" \"y\"}\n{\"b\": 1}\n{\"c\":";
+        let recover = |on_error| {
+            let mut out = Vec::new();
+            obfuscate_json(
+                input.as_bytes(),
+                &mut out,
+                &Rules::default(),
+                true,
+                on_error,
+            )
+            .map(|()| String::from_utf8(out).unwrap())
+        };
+
+        assert_eq!(
+            recover(OnError::Skip).unwrap(),
+            "{\"a\": \"comi\"}\n{\"b\": 1}\n"
+        );
+        assert_eq!(
+            recover(OnError::Passthrough).unwrap(),
+            "{\"a\": \"comi\"}\n{\"t\": \"test\",\n\"x\" \"y\"}\n{\"b\": 1}\n{\"c\":"
+        );
+        match recover(OnError::Fail) {
+            Err(StreamError::Syntax(e)) => assert_eq!((e.line, e.column), (3, 5)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_obfuscate_json_recovers_at_next_element() {
+        let recover = |input: &str, preserve_format, on_error| {
+            let mut out = Vec::new();
+            obfuscate_json(
+                input.as_bytes(),
+                &mut out,
+                &Rules::default(),
+                preserve_format,
+                on_error,
+            )
+            .map(|()| String::from_utf8(out).unwrap())
+        };
+
+        let input = "[{\"a\":\"x\"},{\"a\" \"y\"}]";
+        assert_eq!(
+            recover(input, false, OnError::Skip).unwrap(),
+            "[\n  {\n    \"a\": \"s\"\n  }\n]\n"
+        );
+        assert_eq!(
+            recover(input, true, OnError::Passthrough).unwrap(),
+            "[{\"a\":\"s\"},{\"a\" \"y\"}]"
+        );
+
+        let input = "[1, tru , {\"k\" \"]\"}, \"test\" 2, [3]]\n{\"b\": 1}";
+        assert_eq!(
+            recover(input, true, OnError::Skip).unwrap(),
+            "[1, \"comi\", [3]]\n{\"b\": 1}"
+        );
+        assert_eq!(
+            recover(input, true, OnError::Passthrough).unwrap(),
+            "[1, tru , {\"k\" \"]\"}, \"comi\" 2, [3]]\n{\"b\": 1}"
+        );
+        assert_eq!(
+            recover("[1, {\"a\"", false, OnError::Skip).unwrap(),
+            "[\n  1\n]\n"
+        );
+    }
+}
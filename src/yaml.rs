@@ -0,0 +1,624 @@
+//! In-place YAML obfuscation. A document is parsed into nodes that remember
+//! where they sit in the input, and only the scalars, keys and entries that a
+//! rule or classifier changes are rewritten. Comments, anchors and aliases,
+//! key types, quoting and indentation are kept as written.
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ops::Range;
+use std::slice;
+
+use crate::classifiers::Classifier;
+use crate::numbers::NumberStrategy;
+use crate::rules::{Action, PathSegment, Rules};
+use crate::{obfuscate_key, walk};
+
+/// Obfuscate the YAML documents in `text`, which must already be known to
+/// parse. Returns `None` when a rule deleted every document.
+pub fn obfuscate_yaml(text: &[u8], rules: &Rules) -> Option<Vec<u8>> {
+    let documents = parse(text).expect("document was validated by serde_yaml");
+    let mut editor = Editor {
+        text,
+        rules,
+        path: Vec::new(),
+        edits: Vec::new(),
+    };
+    let mut kept = documents.is_empty();
+    for document in &documents {
+        if editor.node(&document.root, None, None) {
+            kept = true;
+        } else {
+            editor.edit(document.span.clone(), String::new());
+        }
+    }
+    kept.then(|| editor.apply())
+}
+
+/// How a scalar is written in the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Plain,
+    SingleQuoted,
+    DoubleQuoted,
+    /// Literal (`|`) or folded (`>`).
+    Block,
+}
+
+struct Scalar {
+    value: String,
+    style: Style,
+    /// Whether the scalar is tagged `!!str` or `!`.
+    string_tag: bool,
+}
+
+impl Scalar {
+    /// The value the scalar stands for, resolved like `serde_yaml` does for
+    /// untagged plain scalars.
+    fn resolve(&self) -> Value {
+        if self.style != Style::Plain || self.string_tag {
+            return Value::String(self.value.clone());
+        }
+        resolve_plain(&self.value)
+    }
+}
+
+fn resolve_plain(text: &str) -> Value {
+    match text.as_bytes().first() {
+        None => Value::Null,
+        Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'~' | b'n' | b'N' | b't' | b'T' | b'f' | b'F') => {
+            serde_yaml::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+        }
+        Some(_) => Value::String(text.to_string()),
+    }
+}
+
+enum Kind {
+    Scalar(Scalar),
+    Alias,
+    Sequence(Vec<Node>),
+    Mapping(Vec<(Node, Node)>),
+}
+
+struct Node {
+    kind: Kind,
+    /// Start of the node including its anchor and tag.
+    start: usize,
+    /// The node itself, without anchor and tag.
+    span: Range<usize>,
+    /// Whether the node is a flow collection or sits in one.
+    flow: bool,
+}
+
+impl Node {
+    /// Text used for the node as an object key in rule paths.
+    fn name(&self, text: &[u8]) -> String {
+        match &self.kind {
+            Kind::Scalar(scalar) => scalar.value.clone(),
+            _ => String::from_utf8_lossy(&text[self.span.clone()]).into_owned(),
+        }
+    }
+}
+
+struct Document {
+    root: Node,
+    span: Range<usize>,
+}
+
+/// Parse every document in `text`, or return `None` if libyaml rejects it.
+fn parse(text: &[u8]) -> Option<Vec<Document>> {
+    let mut parser = Parser::new(text);
+    let mut documents = Vec::new();
+    loop {
+        let (event, span) = parser.next()?;
+        match event {
+            Event::StreamStart => {}
+            Event::StreamEnd => return Some(documents),
+            Event::DocumentStart => {
+                let (event, node_span) = parser.next()?;
+                let root = build(&mut parser, text, event, node_span, false)?;
+                let (Event::DocumentEnd, end) = parser.next()? else {
+                    return None;
+                };
+                documents.push(Document {
+                    root,
+                    span: span.start..end.end,
+                });
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Build the node opened by `event`, reading the events of its children.
+fn build(
+    parser: &mut Parser,
+    text: &[u8],
+    event: Event,
+    span: Range<usize>,
+    flow: bool,
+) -> Option<Node> {
+    let start = span.start;
+    let node = match event {
+        Event::Alias => Node {
+            kind: Kind::Alias,
+            start,
+            span,
+            flow,
+        },
+        Event::Scalar(scalar, properties) => {
+            let from = match properties {
+                true => skip_properties(text, start, span.end),
+                false => start,
+            };
+            let end = from + text[from..span.end].trim_ascii_end().len();
+            Node {
+                kind: Kind::Scalar(scalar),
+                start,
+                span: from..end,
+                flow,
+            }
+        }
+        Event::SequenceStart(inner) => {
+            let mut items = Vec::new();
+            let end = loop {
+                match parser.next()? {
+                    (Event::SequenceEnd, end) => break end.end,
+                    (event, span) => items.push(build(parser, text, event, span, inner)?),
+                }
+            };
+            let span = match (inner, items.first(), items.last()) {
+                (false, Some(first), Some(last)) => dash(text, first.start)..last.span.end,
+                _ => skip_properties(text, start, end)..end,
+            };
+            Node {
+                kind: Kind::Sequence(items),
+                start,
+                span,
+                flow: inner,
+            }
+        }
+        Event::MappingStart(inner) => {
+            let mut entries = Vec::new();
+            let end = loop {
+                match parser.next()? {
+                    (Event::MappingEnd, end) => break end.end,
+                    (event, span) => {
+                        let key = build(parser, text, event, span, inner)?;
+                        let (event, span) = parser.next()?;
+                        let value = build(parser, text, event, span, inner)?;
+                        entries.push((key, value));
+                    }
+                }
+            };
+            let span = match (inner, entries.first(), entries.last()) {
+                (false, Some((first, _)), Some((_, last))) => first.start..last.span.end,
+                _ => skip_properties(text, start, end)..end,
+            };
+            Node {
+                kind: Kind::Mapping(entries),
+                start,
+                span,
+                flow: inner,
+            }
+        }
+        _ => return None,
+    };
+    Some(node)
+}
+
+/// Skip the anchor and tag in front of a node.
+fn skip_properties(text: &[u8], mut at: usize, end: usize) -> usize {
+    loop {
+        while at < end && text[at].is_ascii_whitespace() {
+            at += 1;
+        }
+        if at >= end || !matches!(text[at], b'&' | b'!') {
+            return at;
+        }
+        while at < end && !text[at].is_ascii_whitespace() {
+            at += 1;
+        }
+    }
+}
+
+/// Position of the `-` introducing the block sequence item at `start`.
+fn dash(text: &[u8], start: usize) -> usize {
+    let before = text[..start].trim_ascii_end();
+    match before.last() {
+        Some(b'-') => before.len() - 1,
+        _ => start,
+    }
+}
+
+/// Replacements collected while walking a document.
+struct Editor<'a> {
+    text: &'a [u8],
+    rules: &'a Rules,
+    path: Vec<PathSegment>,
+    edits: Vec<(Range<usize>, String)>,
+}
+
+impl Editor<'_> {
+    fn edit(&mut self, range: Range<usize>, replacement: String) {
+        self.edits.push((range, replacement));
+    }
+
+    /// The input with every edit applied.
+    fn apply(mut self) -> Vec<u8> {
+        self.edits
+            .sort_by_key(|(range, _)| (range.start, range.end));
+        let mut out = Vec::with_capacity(self.text.len());
+        let mut at = 0;
+        for (range, replacement) in &self.edits {
+            out.extend_from_slice(&self.text[at..range.start]);
+            out.extend_from_slice(replacement.as_bytes());
+            at = range.end;
+        }
+        out.extend_from_slice(&self.text[at..]);
+        out
+    }
+
+    /// Obfuscate the node at the current path, applying its rule like
+    /// [`crate::visit`]. Returns `false` when a rule deleted the node.
+    fn node(
+        &mut self,
+        node: &Node,
+        classifier: Option<&'static Classifier>,
+        numbers: Option<NumberStrategy>,
+    ) -> bool {
+        let (classifier, numbers) = match self.rules.action_for(&self.path) {
+            None => (classifier, numbers),
+            Some(Action::Obfuscate(named, strategy)) => (*named, *strategy),
+            Some(Action::Keep) => return true,
+            Some(Action::Redact(placeholder)) => {
+                self.replace(node, &Value::String(placeholder.clone()));
+                return true;
+            }
+            Some(Action::Null) => {
+                self.replace(node, &Value::Null);
+                return true;
+            }
+            Some(Action::Delete) => return false,
+        };
+        match &node.kind {
+            Kind::Scalar(scalar) => {
+                let original = scalar.resolve();
+                let mut value = original.clone();
+                walk(&mut value, self.rules, &mut self.path, classifier, numbers);
+                if value != original {
+                    self.scalar(node, &value, scalar.style);
+                }
+            }
+            Kind::Alias => {}
+            Kind::Sequence(items) => {
+                let mut kept = Vec::new();
+                for (index, item) in items.iter().enumerate() {
+                    self.path.push(PathSegment::Index(index));
+                    kept.push(self.node(item, classifier, numbers));
+                    self.path.pop();
+                }
+                let entries = items.iter().map(|item| match node.flow {
+                    true => item.start..item.span.end,
+                    false => dash(self.text, item.start)..item.span.end,
+                });
+                self.delete(node, entries.collect(), &kept);
+            }
+            Kind::Mapping(entries) => {
+                let mut renamed = self.rules.obfuscates_keys_at(&self.path).then(HashSet::new);
+                let mut kept = Vec::new();
+                for (key, value) in entries {
+                    self.path.push(PathSegment::Key(key.name(self.text)));
+                    let keep = self.node(value, classifier, numbers);
+                    self.path.pop();
+                    if let (true, Some(taken)) = (keep, &mut renamed) {
+                        self.rename(key, taken);
+                    }
+                    kept.push(keep);
+                }
+                let entries = entries.iter().map(|(key, value)| key.start..value.span.end);
+                self.delete(node, entries.collect(), &kept);
+            }
+        }
+        true
+    }
+
+    /// Rewrite the scalar `node`, keeping its quoting where `value` allows.
+    fn scalar(&mut self, node: &Node, value: &Value, style: Style) {
+        let mut text = render(value, style, node.flow);
+        // An empty value may sit right after its `:` or `-`.
+        if node.span.is_empty() && !self.text[..node.span.start].ends_with(b" ") {
+            text.insert(0, ' ');
+        }
+        self.edit(node.span.clone(), text);
+    }
+
+    /// Write `value` in place of the whole node.
+    fn replace(&mut self, node: &Node, value: &Value) {
+        match &node.kind {
+            Kind::Scalar(scalar) => self.scalar(node, value, scalar.style),
+            Kind::Alias => self.scalar(node, value, Style::Plain),
+            Kind::Sequence(_) | Kind::Mapping(_) => {
+                let text = render(value, Style::Plain, node.flow);
+                self.collection(node, text);
+            }
+        }
+    }
+
+    /// Replace the collection `node` with `text`. A block sequence may start
+    /// in the column of its parent's key, so the replacement is indented to
+    /// stay inside the parent.
+    fn collection(&mut self, node: &Node, text: String) {
+        let text = match (&node.kind, node.flow) {
+            (Kind::Sequence(_), false) => format!("  {}", text),
+            _ => text,
+        };
+        self.edit(node.span.clone(), text);
+    }
+
+    /// Remove the entries of the collection `node` that are not `kept`.
+    /// `entries` spans each entry from its `-` or key to the end of its
+    /// value. A removed run takes the text up to the next kept entry, or
+    /// after the last kept entry when it ends the collection.
+    fn delete(&mut self, node: &Node, entries: Vec<Range<usize>>, kept: &[bool]) {
+        if kept.iter().all(|&k| k) {
+            return;
+        }
+        if !kept.contains(&true) {
+            let empty = match node.kind {
+                Kind::Sequence(_) => "[]",
+                _ => "{}",
+            };
+            match node.flow {
+                true => self.edit(
+                    entries[0].start..entries[entries.len() - 1].end,
+                    String::new(),
+                ),
+                false => self.collection(node, empty.to_string()),
+            }
+            return;
+        }
+        let mut index = 0;
+        while index < kept.len() {
+            if kept[index] {
+                index += 1;
+                continue;
+            }
+            let run = index;
+            while index < kept.len() && !kept[index] {
+                index += 1;
+            }
+            let range = match entries.get(index) {
+                Some(next) => entries[run].start..next.start,
+                None => entries[run - 1].end..entries[index - 1].end,
+            };
+            self.edit(range, String::new());
+        }
+    }
+
+    /// Obfuscate a string key, unless a key it would collide with in
+    /// `taken` is already there. Merge keys (`<<`) and keys of other types
+    /// are kept.
+    fn rename(&mut self, key: &Node, taken: &mut HashSet<String>) {
+        let Kind::Scalar(scalar) = &key.kind else {
+            return;
+        };
+        let Value::String(name) = scalar.resolve() else {
+            return;
+        };
+        if scalar.style == Style::Plain && name == "<<" {
+            return;
+        }
+        let new_name = obfuscate_key(&name, |k| taken.contains(k));
+        if new_name != name {
+            let text = render(&Value::String(new_name.clone()), scalar.style, key.flow);
+            self.edit(key.span.clone(), text);
+        }
+        taken.insert(new_name);
+    }
+}
+
+/// Write `value` as a YAML scalar, in `style` if it can be written that way
+/// and double-quoted otherwise.
+fn render(value: &Value, style: Style, flow: bool) -> String {
+    let Value::String(s) = value else {
+        return value.to_string();
+    };
+    match style {
+        Style::Plain if is_plain_safe(s, flow) => s.clone(),
+        Style::SingleQuoted if !s.contains(|c: char| c.is_control()) => {
+            format!("'{}'", s.replace('\'', "''"))
+        }
+        _ => Value::String(s.clone()).to_string(),
+    }
+}
+
+/// Whether `s` reads back as the same string when written unquoted.
+fn is_plain_safe(s: &str, flow: bool) -> bool {
+    let Some(first) = s.chars().next() else {
+        return false;
+    };
+    !"-?:,[]{}#&*!|>'\"%@`".contains(first)
+        && !first.is_whitespace()
+        && !s.ends_with(char::is_whitespace)
+        && !s.ends_with(':')
+        && !s.contains(": ")
+        && !s.contains(" #")
+        && !s.contains(|c: char| c.is_control())
+        && !(flow && s.contains([',', '[', ']', '{', '}']))
+        && resolve_plain(s) == Value::String(s.to_string())
+}
+
+enum Event {
+    StreamStart,
+    StreamEnd,
+    DocumentStart,
+    DocumentEnd,
+    Alias,
+    /// A scalar and whether it carries an anchor or tag.
+    Scalar(Scalar, bool),
+    /// Start of a sequence, `true` in flow style.
+    SequenceStart(bool),
+    SequenceEnd,
+    /// Start of a mapping, `true` in flow style.
+    MappingStart(bool),
+    MappingEnd,
+}
+
+/// Event reader over libyaml. Events carry the byte range they cover.
+struct Parser<'a> {
+    sys: Box<unsafe_libyaml::yaml_parser_t>,
+    input: PhantomData<&'a [u8]>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        let mut parser = Box::new(MaybeUninit::<unsafe_libyaml::yaml_parser_t>::uninit());
+        // SAFETY: the parser is initialised before use and boxed so it does
+        // not move; `input` outlives it through the `PhantomData` borrow.
+        let sys = unsafe {
+            if unsafe_libyaml::yaml_parser_initialize(parser.as_mut_ptr()).fail {
+                panic!("cannot allocate a YAML parser");
+            }
+            unsafe_libyaml::yaml_parser_set_encoding(
+                parser.as_mut_ptr(),
+                unsafe_libyaml::YAML_UTF8_ENCODING,
+            );
+            unsafe_libyaml::yaml_parser_set_input_string(
+                parser.as_mut_ptr(),
+                input.as_ptr(),
+                input.len() as u64,
+            );
+            parser.assume_init()
+        };
+        Parser {
+            sys,
+            input: PhantomData,
+        }
+    }
+
+    fn next(&mut self) -> Option<(Event, Range<usize>)> {
+        let mut event = MaybeUninit::<unsafe_libyaml::yaml_event_t>::uninit();
+        // SAFETY: a successful `yaml_parser_parse` initialises `event`, whose
+        // strings are copied out before `yaml_event_delete` frees them.
+        unsafe {
+            if unsafe_libyaml::yaml_parser_parse(&mut *self.sys, event.as_mut_ptr()).fail {
+                return None;
+            }
+            let event = event.assume_init_mut();
+            let span = event.start_mark.index as usize..event.end_mark.index as usize;
+            let converted = convert(event);
+            unsafe_libyaml::yaml_event_delete(event);
+            Some((converted, span))
+        }
+    }
+}
+
+impl Drop for Parser<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the parser was initialised in `Parser::new`.
+        unsafe { unsafe_libyaml::yaml_parser_delete(&mut *self.sys) }
+    }
+}
+
+/// Copy what we need out of a libyaml event.
+unsafe fn convert(event: &unsafe_libyaml::yaml_event_t) -> Event {
+    match event.type_ {
+        unsafe_libyaml::YAML_STREAM_START_EVENT => Event::StreamStart,
+        unsafe_libyaml::YAML_STREAM_END_EVENT => Event::StreamEnd,
+        unsafe_libyaml::YAML_DOCUMENT_START_EVENT => Event::DocumentStart,
+        unsafe_libyaml::YAML_DOCUMENT_END_EVENT => Event::DocumentEnd,
+        unsafe_libyaml::YAML_ALIAS_EVENT => Event::Alias,
+        unsafe_libyaml::YAML_SCALAR_EVENT => {
+            let data = unsafe { event.data.scalar };
+            let value = unsafe { slice::from_raw_parts(data.value, data.length as usize) };
+            let tag = unsafe { c_str(data.tag) };
+            let scalar = Scalar {
+                value: String::from_utf8_lossy(value).into_owned(),
+                style: match data.style {
+                    unsafe_libyaml::YAML_PLAIN_SCALAR_STYLE => Style::Plain,
+                    unsafe_libyaml::YAML_SINGLE_QUOTED_SCALAR_STYLE => Style::SingleQuoted,
+                    unsafe_libyaml::YAML_DOUBLE_QUOTED_SCALAR_STYLE => Style::DoubleQuoted,
+                    _ => Style::Block,
+                },
+                string_tag: matches!(tag, Some(b"!" | b"tag:yaml.org,2002:str")),
+            };
+            let properties = tag.is_some() || !data.anchor.is_null();
+            Event::Scalar(scalar, properties)
+        }
+        unsafe_libyaml::YAML_SEQUENCE_START_EVENT => Event::SequenceStart(
+            unsafe { event.data.sequence_start.style } == unsafe_libyaml::YAML_FLOW_SEQUENCE_STYLE,
+        ),
+        unsafe_libyaml::YAML_SEQUENCE_END_EVENT => Event::SequenceEnd,
+        unsafe_libyaml::YAML_MAPPING_START_EVENT => Event::MappingStart(
+            unsafe { event.data.mapping_start.style } == unsafe_libyaml::YAML_FLOW_MAPPING_STYLE,
+        ),
+        unsafe_libyaml::YAML_MAPPING_END_EVENT => Event::MappingEnd,
+        _ => unreachable!("libyaml emitted an empty event"),
+    }
+}
+
+/// Bytes of a NUL-terminated libyaml string, `None` for a null pointer.
+unsafe fn c_str<'a>(ptr: *const u8) -> Option<&'a [u8]> {
+    if ptr.is_null() {
+        return None;
+    }
+    Some(unsafe { std::ffi::CStr::from_ptr(ptr.cast()) }.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_strings;
+
+    fn yaml(input: &str, rules: &Rules) -> String {
+        String::from_utf8(obfuscate_yaml(input.as_bytes(), rules).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_obfuscate_yaml_matches_whole_value_walk() {
+        let rules = Rules::parse(
+            "rules:\n  - path: $[*].kept\n    action: keep\n  - path: $[*].gone\n    action: delete\n  - path: \"**.secret\"\n    action: redact\n  - path: $[*].cleared\n    action: \"null\"\n  - path: $[*].count\n    action: obfuscate\n    numbers: remap\nobfuscate_keys:\n  - $[*].users\n",
+        )
+        .unwrap();
+        let input = "- cleared: {a: 1}\n  count: 1234\n  empty: []\n  gone:\n  - 1\n  - 2\n  kept:\n    name: Alice\n  name: 'Alice'\n  nested:\n  - [~, true, -1.5e3]\n  - \"Word\"\n  none: {}\n  secret: hunter2\n  users:\n    alice: {role: admin}\n- lowercase\n";
+
+        let mut expected: Value = serde_yaml::from_str(input).unwrap();
+        assert!(hash_strings(&mut expected, &rules));
+        let out = yaml(input, &rules);
+        assert_eq!(
+            serde_yaml::from_str::<Value>(&out).unwrap(),
+            expected,
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn test_obfuscate_yaml_keeps_layout() {
+        let input = "# owner: alice\nbase: &base\n  name: alice   # inline\n  port: 8080\n1: z\ntrue: \"quoted\"\nlist: [x, *base]\nmerged:\n  <<: *base\n  note: |\n    line one\n";
+        assert_eq!(
+            yaml(input, &Rules::default()),
+            "# owner: alice\nbase: &base\n  name: upwit   # inline\n  port: 8080\n1: v\ntrue: \"bemost\"\nlist: [s, *base]\nmerged:\n  <<: *base\n  note: \"pres enb\\n\"\n"
+        );
+    }
+
+    #[test]
+    fn test_obfuscate_yaml_deletes_entries() {
+        let rules = Rules::parse("rules:\n  - path: \"**.x\"\n    action: delete\n  - path: \"**.y\"\n    action: delete\n").unwrap();
+        let input =
+            "a:\n  x: 1\n  b: 2\n  y: 3\nc: {x: 1, d: 2}\ne: [{x: 1}]\nf:\n- x: 1\ng:\n  x: [1]\n";
+        assert_eq!(
+            yaml(input, &rules),
+            "a:\n  b: 2\nc: {d: 2}\ne: [{}]\nf:\n- {}\ng:\n  {}\n"
+        );
+    }
+
+    #[test]
+    fn test_obfuscate_yaml_renames_string_keys_only() {
+        let rules = Rules::parse("obfuscate_keys:\n  - \"$\"\n").unwrap();
+        let input = "alice: 1\n1: 2\n\"bob\": 3\n<<: {}\n";
+        assert_eq!(yaml(input, &rules), "upwit: 1\n1: 2\n\"hav\": 3\n<<: {}\n");
+    }
+}
@@ -18,15 +18,131 @@ pub const WELL_KNOWN_INPUTS: &[Example] = &[
     },
     Example {
         input: "snake_case_word",
-        detectors: &["snake_case_word"],
+        detectors: &["snake_case_word", "identifier"],
+    },
+    Example {
+        input: "Müller",
+        detectors: &["unicode_word"],
+    },
+    Example {
+        input: "Ренат",
+        detectors: &["unicode_word"],
+    },
+    Example {
+        input: "Αθήνα",
+        detectors: &["unicode_word"],
+    },
+    Example {
+        input: "東京",
+        detectors: &["unicode_word"],
+    },
+    Example {
+        input: "4111 1111 1111 1111",
+        detectors: &["card_number", "digit_pattern"],
+    },
+    Example {
+        input: "GB82 WEST 1234 5698 7654 32",
+        detectors: &["iban", "prose"],
+    },
+    Example {
+        input: "978-3-16-148410-0",
+        detectors: &["isbn", "digit_pattern"],
+    },
+    Example {
+        input: "+1 (415) 555-0132",
+        detectors: &["phone_number", "digit_pattern"],
+    },
+    Example {
+        input: "1,234.56",
+        detectors: &["amount", "digit_pattern"],
+    },
+    Example {
+        input: "000123",
+        detectors: &["numeric_code", "digit_pattern"],
+    },
+    Example {
+        input: "94107-1234",
+        detectors: &["digit_pattern"],
+    },
+    Example {
+        input: "camelCaseWord",
+        detectors: &["identifier"],
+    },
+    Example {
+        input: "PascalCaseWord",
+        detectors: &["identifier"],
+    },
+    Example {
+        input: "kebab-case-word",
+        detectors: &["identifier"],
+    },
+    Example {
+        input: "SCREAMING_SNAKE_WORD",
+        detectors: &["identifier"],
     },
     Example {
         input: "A Title Case Sentence",
-        detectors: &["title_case_sentence"],
+        detectors: &["title_case_sentence", "prose"],
+    },
+    Example {
+        input: "Please call me back before 5pm, thanks!",
+        detectors: &["prose"],
     },
     Example {
         input: "2022-05-16T22:39:20Z",
-        detectors: &["iso8601_z_datetime"],
+        detectors: &["iso8601_z_datetime", "rfc3339_datetime"],
+    },
+    Example {
+        input: "2022-05-16T22:39:20.123+02:00",
+        detectors: &["rfc3339_datetime"],
+    },
+    Example {
+        input: "2022-05-16T22:39:20.5Z",
+        detectors: &["rfc3339_datetime"],
+    },
+    Example {
+        input: "2022-05-16",
+        detectors: &["iso8601_date", "digit_pattern"],
+    },
+    Example {
+        input: "Tue, 1 Jul 2003 10:52:37 +0200",
+        detectors: &["rfc2822_datetime", "prose"],
+    },
+    Example {
+        input: "1652740760",
+        detectors: &["epoch_seconds", "numeric_code", "digit_pattern"],
+    },
+    Example {
+        input: "1652740760123",
+        detectors: &["epoch_millis", "numeric_code", "digit_pattern"],
+    },
+    Example {
+        input: "alice.smith+news@corp.example",
+        detectors: &["email"],
+    },
+    Example {
+        input: "https://example.com/accounts/6d407c4c7578c31bdbe1dce529476c1a/signInPassword",
+        detectors: &["url"],
+    },
+    Example {
+        input: "203.0.113.7",
+        detectors: &["ip_address", "digit_pattern"],
+    },
+    Example {
+        input: "2001:db8:85a3::8a2e:370:7334",
+        detectors: &["ip_address"],
+    },
+    Example {
+        input: "f47ac10b-58cc-4372-a567-0e02b2c3d479",
+        detectors: &["uuid"],
+    },
+    Example {
+        input: "6d407c4c7578c31bdbe1dce529476c1a",
+        detectors: &["hex_lowercase"],
+    },
+    Example {
+        input: "DA39A3EE5E6B4B0D3255BFEF95601890AFD80709",
+        detectors: &["hex_uppercase"],
     },
     Example {
         input: "mfrggzdfmztwq2lknnwg23tp",